@@ -77,8 +77,12 @@ impl App {
                 self.debug.perf.add_frame_time(duration.as_millis());
             }
 
-            self.render
-                .update_frame(nes.get_frame_buffer(), &self.config.overscan);
+            // Also redraw a paused frame when the overscan settings change
+            if nes.framebuffer_changed_this_frame() || self.render.overscan != self.config.overscan
+            {
+                self.render
+                    .update_frame(nes.get_frame_buffer(), &self.config.overscan);
+            }
         }
     }
 
@@ -1,3 +1,5 @@
+use fearless_nes::StopReason;
+
 use crate::app::Gui;
 
 mod cartridge_info;
@@ -10,6 +12,8 @@ use crate::App;
 
 pub struct Debug {
     pub show_controls: bool,
+    pub instruction_count: u64,
+    pub stop_reason: Option<StopReason>,
     pub cartridge_info: CartridgeInfo,
     pub ppu: Ppu,
     pub perf: Perf,
@@ -19,6 +23,8 @@ impl Debug {
     pub fn new() -> Self {
         Self {
             show_controls: false,
+            instruction_count: 1,
+            stop_reason: None,
             cartridge_info: CartridgeInfo::new(),
             ppu: Ppu::new(),
             perf: Perf::new(),
@@ -30,6 +36,8 @@ impl Gui for Debug {
     fn gui_window(app: &mut super::App, egui_ctx: &egui::CtxRef) {
         if let Some(ref mut nes) = app.nes {
             let paused = &mut app.paused;
+            let instruction_count = &mut app.debug.instruction_count;
+            let stop_reason = &mut app.debug.stop_reason;
 
             if app.debug.show_controls {
                 egui::Window::new("Controls and Status")
@@ -46,6 +54,28 @@ impl Gui for Debug {
                             nes.run_cpu_cycle();
                         }
 
+                        ui.horizontal(|ui| {
+                            ui.add(
+                                egui::DragValue::new(instruction_count).clamp_range(1..=1_000_000),
+                            );
+                            if ui.button("Run N instructions").clicked() {
+                                *paused = true;
+                                *stop_reason = Some(nes.run_instructions(*instruction_count));
+                            }
+                        });
+                        ui.label(format!("PC: ${:04X}", nes.get_pc()));
+                        if let Some(StopReason::Breakpoint(pc)) = stop_reason {
+                            ui.label(format!("Stopped at breakpoint ${:04X}", pc));
+                        }
+
+                        let mut volume = nes.get_master_volume();
+                        if ui
+                            .add(egui::Slider::new(&mut volume, 0.0..=2.0).text("Volume"))
+                            .changed()
+                        {
+                            nes.set_master_volume(volume);
+                        }
+
                         ui.label(format!("Frame count: {}", nes.get_frame_count()));
                         ui.label(format!("CPU cycle count: {}", nes.get_cycle_count()));
                     });
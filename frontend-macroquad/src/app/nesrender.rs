@@ -6,6 +6,10 @@ use fearless_nes::PALETTE;
 
 pub struct NesRender {
     pub image: Image,
+    /// The image has changed since it was last uploaded to the texture
+    image_changed: bool,
+    /// The overscan the image was drawn with
+    pub overscan: Overscan,
     texture: Texture2D,
     scale: f32,
     draw_pos: f32,
@@ -19,6 +23,8 @@ impl NesRender {
 
         let mut s = Self {
             image,
+            image_changed: false,
+            overscan: Overscan::new(),
             texture,
             scale: 0.,
             draw_pos: 0.,
@@ -50,12 +56,18 @@ impl NesRender {
 
             self.image.set_pixel(x, y, color);
         }
+
+        self.image_changed = true;
+        self.overscan = *overscan;
     }
 
     pub fn draw_nes(&mut self) {
         self.recalc_draw_pos();
 
-        self.texture.update(&self.image);
+        if self.image_changed {
+            self.texture.update(&self.image);
+            self.image_changed = false;
+        }
 
         draw_texture_ex(
             self.texture,
@@ -81,7 +93,7 @@ impl NesRender {
     }
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, Clone, Copy, PartialEq)]
 pub struct Overscan {
     pub top: u32,
     pub right: u32,
@@ -133,13 +133,13 @@ impl Saves {
         Ok(())
     }
 
-    pub fn load_zipped_save(savefile: &File) -> Result<Nes> {
+    pub fn load_zipped_save(savefile: &File, nes: &mut Nes) -> Result<()> {
         let mut save_archive = zip::ZipArchive::new(savefile)?;
         let mut savestate_zip = save_archive.by_name(SAVESTATE_PATH)?;
         let mut savestate = Vec::with_capacity(savestate_zip.size() as usize);
         savestate_zip.read_to_end(&mut savestate)?;
 
-        Ok(Nes::load_state(&savestate)?)
+        Ok(nes.load_state(&savestate)?)
     }
 }
 
@@ -182,12 +182,15 @@ impl Gui for Saves {
                                     ))
                                     .clicked()
                                 {
-                                    match Self::load_zipped_save(&save.file) {
-                                        Ok(n) => *nes = Some(n),
-                                        Err(e) => report_error(&format!(
+                                    let result = match nes {
+                                        Some(nes) => Self::load_zipped_save(&save.file, nes),
+                                        None => Err(anyhow!("no game is loaded")),
+                                    };
+                                    if let Err(e) = result {
+                                        report_error(&format!(
                                             "Couldn't load the save file. Error: {}",
                                             e
-                                        )),
+                                        ));
                                     }
                                 };
 
@@ -1,27 +1,80 @@
 use serde::{Deserialize, Serialize};
 
-use super::Nes;
+use super::{snapshot::ApuSnapshot, Nes};
+use crate::cartridge::Region;
 
+/// CPU cycles per output sample, unless [`NesBuilder::sample_rate`](crate::NesBuilder::sample_rate)
+/// is set
 static SAMPLE_FREQ: u32 = 40;
+/// Oldest samples get dropped when the frontend doesn't consume the audio buffer
+const AUDIO_BUFFER_CAPACITY: usize = 0x10000;
+
+/// Pending APU interrupts, see [`Nes::apu_irq_pending`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ApuIrq {
+    pub frame: bool,
+    pub dmc: bool,
+}
+
+/// Read-only view of the DMC channel, see [`Nes::dmc_state`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DmcState {
+    pub bytes_remaining: u16,
+    /// Address of the next sample byte
+    pub current_address: u16,
+    pub output_level: u8,
+    /// Whether the fetched sample byte is waiting to be played
+    pub buffer_filled: bool,
+    /// Same as $4015 bit 4, the sample still has bytes to fetch
+    pub playing: bool,
+}
+
+/// See [`Nes::set_channel_pan`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AudioChannel {
+    Pulse1 = 0,
+    Pulse2 = 1,
+    Triangle = 2,
+    Noise = 3,
+    Dmc = 4,
+}
 
 #[derive(Serialize, Deserialize)]
 pub struct Apu {
     cycles: u16,
-    sample_counter: u32,
+    /// CPU cycles per output sample
+    sample_period: f64,
+    sample_counter: f64,
 
     pulse_1: Pulse<1>,
     pulse_2: Pulse<0>,
-    triangle: Triangle,
+    pub(crate) triangle: Triangle,
     noise: Noise,
-    dmc: Dmc,
+    pub(crate) dmc: Dmc,
     frame_counter: FrameCounter,
+    /// The last value written to each register from $4000 to $4017
+    register_shadow: [u8; 0x18],
 
     pulse_table: Vec<f32>,
     tnd_table: Vec<f32>,
+
+    /// Multiplies the mixer output
+    master_volume: f32,
+    /// Output interleaved left / right samples
+    stereo: bool,
+    /// -1 (left) to 1 (right) for each channel, in the order of [`AudioChannel`]
+    pans: [f32; 5],
+    #[serde(skip)]
+    audio_buffer: Vec<f32>,
+
+    /// Mixer inputs of each channel (pulse 1, pulse 2, triangle, noise, DMC), only kept while
+    /// a multitrack recording is running
+    #[serde(skip)]
+    multitrack: Option<Box<[Vec<f32>; 5]>>,
 }
 
 impl Apu {
-    pub(crate) fn new() -> Apu {
+    pub(crate) fn new(region: Region, sample_rate: Option<u32>) -> Apu {
         let mut pulse_table = vec![0f32; 31];
         for n in 0..31 {
             pulse_table[n] = 95.52 / (8128f32 / n as f32 + 100f32);
@@ -34,16 +87,28 @@ impl Apu {
 
         Apu {
             cycles: 0,
-            sample_counter: 0,
+            sample_period: match sample_rate {
+                Some(sample_rate) => region.cpu_clock_rate() / f64::from(sample_rate),
+                None => f64::from(SAMPLE_FREQ),
+            },
+            sample_counter: 0.,
             pulse_1: Pulse::new(),
             pulse_2: Pulse::new(),
             triangle: Triangle::new(),
             noise: Noise::new(),
             dmc: Dmc::new(),
             frame_counter: FrameCounter::new(),
+            register_shadow: [0; 0x18],
 
             pulse_table,
             tnd_table,
+
+            master_volume: 1.,
+            stereo: false,
+            pans: [0.; 5],
+            audio_buffer: Vec::new(),
+
+            multitrack: None,
         }
     }
 }
@@ -57,18 +122,30 @@ impl Nes {
             self.apu.pulse_2.clock();
         }
 
+        self.apu.triangle.clock();
+        self.apu.dmc.clock();
+
         // Use CPU cycles so I can get "half-APU-cycle" timing correct...
         self.apu.cycles = self.apu.cycles.wrapping_add(1);
 
+        if self.apu.frame_counter.reset_delay > 0 {
+            self.apu.frame_counter.reset_delay -= 1;
+            if self.apu.frame_counter.reset_delay == 0 {
+                self.apu.cycles = 0;
+            }
+        }
+
         if self.apu.frame_counter.mode {
             match self.apu.cycles {
                 7457 => {
-                    //TODO: clock noise envelopes and triangle linear counter
+                    //TODO: clock noise envelopes
+                    self.apu.triangle.linear_clock();
                     self.apu.pulse_1.envelope.clock();
                     self.apu.pulse_2.envelope.clock();
                 }
                 14913 => {
-                    //TODO: clock noise envelopes and triangle linear counter
+                    //TODO: clock noise envelopes
+                    self.apu.triangle.linear_clock();
                     self.apu.pulse_1.frame_clock();
                     self.apu.pulse_2.frame_clock();
 
@@ -76,12 +153,14 @@ impl Nes {
                     self.apu.triangle.length_counter.clock();
                 }
                 22371 => {
-                    //TODO: clock noise envelopes and triangle linear counter
+                    //TODO: clock noise envelopes
+                    self.apu.triangle.linear_clock();
                     self.apu.pulse_1.envelope.clock();
                     self.apu.pulse_2.envelope.clock();
                 }
                 37281 => {
-                    //TODO: clock noise envelopes and triangle linear counter
+                    //TODO: clock noise envelopes
+                    self.apu.triangle.linear_clock();
                     self.apu.pulse_1.frame_clock();
                     self.apu.pulse_2.frame_clock();
 
@@ -96,14 +175,16 @@ impl Nes {
         } else {
             match self.apu.cycles {
                 0 => {
-                    //TODO: clock noise envelopes and triangle linear counter
+                    //TODO: clock noise envelopes
                 }
                 7457 => {
-                    //TODO: clock noise envelopes and triangle linear counter
+                    //TODO: clock noise envelopes
+                    self.apu.triangle.linear_clock();
                     self.apu.pulse_1.envelope.clock();
                     self.apu.pulse_2.envelope.clock();
                 }
                 14913 => {
+                    self.apu.triangle.linear_clock();
                     self.apu.pulse_1.frame_clock();
                     self.apu.pulse_2.frame_clock();
 
@@ -111,29 +192,25 @@ impl Nes {
                     self.apu.triangle.length_counter.clock();
                 }
                 22371 => {
+                    self.apu.triangle.linear_clock();
                     self.apu.pulse_1.envelope.clock();
                     self.apu.pulse_2.envelope.clock();
                 }
                 29828 => {
-                    /* if !self.apu.frame_counter.irq_inhibit {
-                        self.cpu.irq_signal = true;
-                    } */
+                    self.apu.frame_counter.raise_irq();
                 }
                 29829 => {
+                    self.apu.triangle.linear_clock();
                     self.apu.pulse_1.frame_clock();
                     self.apu.pulse_2.frame_clock();
 
                     self.apu.noise.length_counter.clock();
                     self.apu.triangle.length_counter.clock();
 
-                    /* if !self.apu.frame_counter.irq_inhibit {
-                        self.cpu.irq_signal = true;
-                    } */
+                    self.apu.frame_counter.raise_irq();
                 }
                 29830 => {
-                    /* if !self.apu.frame_counter.irq_inhibit {
-                        self.cpu.irq_signal = true;
-                    } */
+                    self.apu.frame_counter.raise_irq();
 
                     self.apu.cycles = 0;
                 }
@@ -141,15 +218,62 @@ impl Nes {
             }
         }
 
-        self.apu.sample_counter += 1;
-        if self.apu.sample_counter == SAMPLE_FREQ {
-            self.apu.sample_counter = 0;
-            let _output = self.mixer();
+        self.apu.pulse_1.length_counter.apply_writes();
+        self.apu.pulse_2.length_counter.apply_writes();
+        self.apu.triangle.length_counter.apply_writes();
+        self.apu.noise.length_counter.apply_writes();
+
+        self.apu.sample_counter += 1.;
+        if self.apu.sample_counter >= self.apu.sample_period {
+            self.apu.sample_counter -= self.apu.sample_period;
+            let (output, channels) = self.mixer();
+
+            if self.apu.stereo {
+                let (left, right) = self.pan(output, &channels);
+                self.push_sample(left);
+                self.push_sample(right);
+            } else {
+                self.push_sample(output);
+            }
+        }
+    }
+
+    #[inline]
+    fn push_sample(&mut self, sample: f32) {
+        // The capacity is even, so stereo samples stay interleaved in the right order
+        if self.apu.audio_buffer.len() >= AUDIO_BUFFER_CAPACITY {
+            self.apu.audio_buffer.drain(..AUDIO_BUFFER_CAPACITY / 2);
+        }
+        self.apu
+            .audio_buffer
+            .push((sample * self.apu.master_volume).clamp(-1., 1.));
+    }
+
+    /// Splits the mixed output to the left and right channels according to the contributions of
+    /// each channel and its pan. A centered channel plays at full volume on both sides.
+    fn pan(&self, output: f32, channels: &[f32; 5]) -> (f32, f32) {
+        let total: f32 = channels.iter().sum();
+        if total <= 0. {
+            return (output, output);
         }
+
+        // Rescale the contributions so they add up to the non-linear mixer output
+        let scale = output / total;
+        channels
+            .iter()
+            .zip(&self.apu.pans)
+            .fold((0., 0.), |(left, right), (channel, pan)| {
+                let channel = channel * scale;
+                (
+                    left + channel * (1. - pan).min(1.),
+                    right + channel * (1. + pan).min(1.),
+                )
+            })
     }
 
+    /// Returns the mixed output and the contribution of each channel
     #[inline]
-    fn mixer(&mut self) -> f32 {
+    fn mixer(&mut self) -> (f32, [f32; 5]) {
         //The APU mixer formulas can be efficiently implemented using two lookup tables: a 31-entry table
         //for the two pulse channels and a 203-entry table for the remaining channels (due to the approximation
         //of tnd_out, the numerators are adjusted slightly to preserve the normalized output range).
@@ -170,17 +294,170 @@ impl Nes {
         let pulse_2 = self.apu.pulse_2.output() as usize;
         let pulse_out = self.apu.pulse_table[pulse_1 + pulse_2];
 
-        let triangle = 0;
+        let triangle = self.apu.triangle.output() as usize;
         let noise = 0;
-        let dmc = 0;
+        let dmc = self.apu.dmc.output_level as usize;
         let tnd_out = self.apu.tnd_table[3 * triangle + 2 * noise + dmc];
 
-        pulse_out + tnd_out
+        // Every channel goes through its own mixer table, so the contributions roughly add up
+        // to the mixed output
+        let channels = [
+            self.apu.pulse_table[pulse_1],
+            self.apu.pulse_table[pulse_2],
+            self.apu.tnd_table[3 * triangle],
+            self.apu.tnd_table[2 * noise],
+            self.apu.tnd_table[dmc],
+        ];
+
+        if let Some(tracks) = &mut self.apu.multitrack {
+            for (track, channel) in tracks.iter_mut().zip(channels) {
+                track.push(channel);
+            }
+        }
+
+        (pulse_out + tnd_out, channels)
+    }
+
+    /// State of the APU's IRQ output, which is combined with the cartridge IRQ line
+    #[inline]
+    pub(crate) fn apu_irq_line(&self) -> bool {
+        self.apu.frame_counter.irq_flag || self.apu.dmc.irq_flag
+    }
+
+    /// Sets the DMC IRQ flag directly, regardless of the IRQ enable flag
+    pub(crate) fn apu_set_dmc_irq(&mut self, state: bool) {
+        self.apu.dmc.irq_flag = state;
+    }
+
+    /// Sets the frame IRQ flag directly, regardless of the IRQ inhibit flag
+    pub(crate) fn apu_set_frame_irq(&mut self, state: bool) {
+        self.apu.frame_counter.irq_flag = state;
+    }
+
+    /// Same as the IRQ flags in $4015, but without acknowledging the frame IRQ
+    pub(crate) fn apu_irq_flags(&self) -> ApuIrq {
+        ApuIrq {
+            frame: self.apu.frame_counter.irq_flag,
+            dmc: self.apu.dmc.irq_flag,
+        }
+    }
+
+    pub(crate) fn apu_dmc_state(&self) -> DmcState {
+        let dmc = &self.apu.dmc;
+        DmcState {
+            bytes_remaining: dmc.bytes_remaining,
+            current_address: dmc.current_address,
+            output_level: dmc.output_level,
+            buffer_filled: dmc.sample_buffer.is_some(),
+            playing: dmc.bytes_remaining > 0,
+        }
+    }
+
+    pub(crate) fn apu_register_values(&self) -> [u8; 0x18] {
+        self.apu.register_shadow
+    }
+
+    pub(crate) fn apu_snapshot(&self) -> ApuSnapshot {
+        let apu = &self.apu;
+        ApuSnapshot {
+            pulse_1_length: apu.pulse_1.length_counter.counter,
+            pulse_1_volume: apu.pulse_1.envelope.volume(),
+            pulse_2_length: apu.pulse_2.length_counter.counter,
+            pulse_2_volume: apu.pulse_2.envelope.volume(),
+            triangle_length: apu.triangle.length_counter.counter,
+            triangle_linear_counter: apu.triangle.linear_counter,
+            noise_length: apu.noise.length_counter.counter,
+            noise_volume: apu.noise.volume,
+            dmc_bytes_remaining: apu.dmc.bytes_remaining,
+            dmc_output_level: apu.dmc.output_level,
+            frame_irq: apu.frame_counter.irq_flag,
+        }
+    }
+
+    /// The DMC wants to refill its sample buffer
+    #[inline]
+    pub(crate) fn apu_dmc_dma_pending(&self) -> bool {
+        self.apu.dmc.sample_buffer.is_none() && self.apu.dmc.bytes_remaining > 0
+    }
+
+    #[inline]
+    pub(crate) fn apu_dmc_address(&self) -> u16 {
+        self.apu.dmc.current_address
+    }
+
+    #[inline]
+    pub(crate) fn apu_dmc_fill(&mut self, val: u8) {
+        self.apu.dmc.fill(val);
+    }
+
+    pub(crate) fn apu_set_master_volume(&mut self, gain: f32) {
+        self.apu.master_volume = gain.max(0.);
+    }
+
+    pub(crate) fn apu_master_volume(&self) -> f32 {
+        self.apu.master_volume
+    }
+
+    /// Master volume, stereo mode and panning
+    pub(crate) fn apu_copy_output_settings(&mut self, from: &Apu) {
+        self.apu.master_volume = from.master_volume;
+        self.apu.stereo = from.stereo;
+        self.apu.pans = from.pans;
+    }
+
+    pub(crate) fn apu_set_stereo(&mut self, stereo: bool) {
+        self.apu.stereo = stereo;
+    }
+
+    pub(crate) fn apu_set_pan(&mut self, channel: AudioChannel, pan: f32) {
+        self.apu.pans[channel as usize] = pan.clamp(-1., 1.);
+    }
+
+    pub(crate) fn apu_audio_buffer(&self) -> &[f32] {
+        &self.apu.audio_buffer
+    }
+
+    pub(crate) fn apu_clear_audio_buffer(&mut self) {
+        self.apu.audio_buffer.clear();
+    }
+
+    /// Samples in the audio buffer, a stereo sample counts once
+    pub(crate) fn apu_buffered_samples(&self) -> usize {
+        self.apu.audio_buffer.len() / self.apu_channel_count()
+    }
+
+    /// Samples that fit in the audio buffer before the oldest ones get dropped
+    pub(crate) fn apu_audio_capacity(&self) -> usize {
+        AUDIO_BUFFER_CAPACITY / self.apu_channel_count()
+    }
+
+    fn apu_channel_count(&self) -> usize {
+        if self.apu.stereo {
+            2
+        } else {
+            1
+        }
+    }
+
+    pub(crate) fn apu_start_multitrack(&mut self) {
+        self.apu.multitrack = Some(Default::default());
+    }
+
+    pub(crate) fn apu_stop_multitrack(&mut self) -> [Vec<f32>; 5] {
+        self.apu
+            .multitrack
+            .take()
+            .map(|tracks| *tracks)
+            .unwrap_or_default()
     }
 
     /// https://wiki.nesdev.org/w/index.php?title=APU_registers
     #[inline]
     pub(crate) fn apu_write_reg(&mut self, addr: usize, val: u8) {
+        if let Some(shadow) = self.apu.register_shadow.get_mut(addr - 0x4000) {
+            *shadow = val;
+        }
+
         match addr {
             0x4000 => self.apu.pulse_1.set_dlcv(val),
             0x4001 => self.apu.pulse_1.set_epns(val),
@@ -191,9 +468,13 @@ impl Nes {
             0x4006 => self.apu.pulse_2.set_t(val),
             0x4007 => self.apu.pulse_2.set_lt(val),
             0x4008 => self.apu.triangle.set_c(val),
+            // Unused
+            0x4009 => (),
             0x400A => self.apu.triangle.set_tl(val),
             0x400B => self.apu.triangle.set_l(val),
             0x400C => self.apu.noise.set_lcn(val),
+            // Unused
+            0x400D => (),
             0x400E => self.apu.noise.set_lp(val),
             0x400F => self.apu.noise.set_l(val),
             0x4010 => self.apu.dmc.set_ilf(val),
@@ -207,17 +488,24 @@ impl Nes {
                 without clocking any of its units. */
 
                 if val & 0x80 != 0 {
+                    self.apu.triangle.linear_clock();
                     self.apu.pulse_1.frame_clock();
                     self.apu.pulse_2.frame_clock();
 
                     self.apu.noise.length_counter.clock();
                     self.apu.triangle.length_counter.clock();
                 }
-                // TODO: reset APU cycles on chaning frame counter
+                // The sequence is reset 3 CPU cycles after the write if it happens during an APU
+                // cycle, 4 cycles after it otherwise
+                self.apu.frame_counter.reset_delay = if self.cpu.odd_cycle { 4 } else { 3 };
 
-                self.apu.frame_counter.set_mi(val, &mut self.cpu.irq_signal)
+                self.apu.frame_counter.set_mi(val)
+            }
+            _ => {
+                if let Some(hook) = &mut self.unimplemented_write_hook {
+                    hook(addr as u16, val);
+                }
             }
-            _ => (),
         }
     }
 
@@ -241,22 +529,38 @@ impl Nes {
             result |= 8;
         }
 
-        //TODO: set DMC active bit
+        if self.apu.dmc.bytes_remaining > 0 {
+            result |= 0x10;
+        }
 
-        if self.apu.frame_counter.irq_inhibit {
+        // A flag that is set on the same cycle as the read reads back as set, but isn't cleared
+        let frame_irq_being_set = self.apu_frame_irq_being_set();
+        if self.apu.frame_counter.irq_flag || frame_irq_being_set {
             result |= 0x40;
         }
 
-        if self.apu.dmc.irq_enable {
+        if self.apu.dmc.irq_flag {
             result |= 0x80;
         }
 
-        self.apu.frame_counter.irq_inhibit = false;
-        self.cpu.irq_signal = false;
+        if !frame_irq_being_set {
+            self.apu.frame_counter.irq_flag = false;
+        }
+        self.apu.dmc.irq_flag = false;
 
         result
     }
 
+    /// Whether the 4-step sequence raises the frame IRQ flag at the end of the current CPU cycle,
+    /// it's raised on 3 consecutive cycles
+    #[inline]
+    fn apu_frame_irq_being_set(&self) -> bool {
+        let frame_counter = &self.apu.frame_counter;
+        !frame_counter.mode
+            && !frame_counter.irq_inhibit
+            && (29827..=29829).contains(&self.apu.cycles)
+    }
+
     //$4015 write ---D NT21   Enable DMC (D), noise (N), triangle (T), and pulse channels (2/1)
     //Writing a zero to any of the channel enable bits will silence that channel and immediately set its length counter to 0.
     //If the DMC bit is clear, the DMC bytes remaining will be set to 0 and the DMC will silence when it empties.
@@ -264,36 +568,27 @@ impl Nes {
     //Writing to this register clears the DMC interrupt flag.
     #[inline]
     fn apu_write_status(&mut self, val: u8) {
-        self.apu.dmc.irq_enable = false;
+        self.apu.dmc.irq_flag = false;
 
-        let _d = val & 0x10 != 0;
+        let d = val & 0x10 != 0;
         let n = val & 8 != 0;
         let t = val & 4 != 0;
         let p_2 = val & 2 != 0;
         let p_1 = val & 1 != 0;
 
-        //TODO: manage DMC
+        if !d {
+            self.apu.dmc.bytes_remaining = 0;
+        } else if self.apu.dmc.bytes_remaining == 0 {
+            self.apu.dmc.restart();
+        }
 
         if !n {
             self.apu.noise.volume = 0;
-            self.apu.noise.length_counter.counter = 0;
-        }
-        self.apu.noise.length_counter.enabled = n;
-
-        if !t {
-            self.apu.triangle.length_counter.counter = 0;
-        }
-        self.apu.triangle.length_counter.enabled = t;
-
-        if !p_2 {
-            self.apu.pulse_2.length_counter.counter = 0;
-        }
-        self.apu.pulse_2.length_counter.enabled = p_2;
-
-        if !p_1 {
-            self.apu.pulse_1.length_counter.counter = 0;
         }
-        self.apu.pulse_1.length_counter.enabled = p_1;
+        self.apu.noise.length_counter.set_enabled(n);
+        self.apu.triangle.length_counter.set_enabled(t);
+        self.apu.pulse_2.length_counter.set_enabled(p_2);
+        self.apu.pulse_1.length_counter.set_enabled(p_1);
     }
 }
 
@@ -322,6 +617,8 @@ struct Pulse<const ADDER: u16> {
     envelope: Envelope,
 
     sweep: Sweep<ADDER>,
+    /// Counts down from the timer period (kept by the sweep unit) every APU cycle
+    timer: u16,
     length_counter: LengthCounter,
     //enabled: bool,
 }
@@ -334,6 +631,7 @@ impl<const ADDER: u16> Pulse<ADDER> {
             envelope: Envelope::new(),
 
             sweep: Sweep::new(),
+            timer: 0,
             length_counter: LengthCounter::new(),
             //enabled: false,
         }
@@ -342,7 +640,7 @@ impl<const ADDER: u16> Pulse<ADDER> {
     #[inline]
     fn set_dlcv(&mut self, val: u8) {
         self.duty_seq = (val & 0xC0) >> 3;
-        self.length_counter.enabled = (val & 0x20) == 0;
+        self.length_counter.set_halt(val & 0x20 != 0);
         self.envelope._loop = (val & 0x20) != 0;
         self.envelope.constant_volume = (val & 0x10) != 0;
         self.envelope.period = val & 0xF;
@@ -355,23 +653,23 @@ impl<const ADDER: u16> Pulse<ADDER> {
 
     #[inline]
     fn set_t(&mut self, val: u8) {
-        self.sweep.timer = (self.sweep.timer & !0xFF) | u16::from(val);
+        self.sweep.period = (self.sweep.period & !0xFF) | u16::from(val);
     }
 
     #[inline]
     fn set_lt(&mut self, val: u8) {
         self.duty_cycle = 0;
         self.length_counter.load((val & 0xF8) >> 3);
-        self.sweep.timer = (self.sweep.timer & !0x700) | (u16::from(val & 7) << 8);
+        self.sweep.period = (self.sweep.period & !0x700) | (u16::from(val & 7) << 8);
     }
 
     #[inline]
     fn clock(&mut self) {
-        if self.sweep.timer > 0 {
-            self.sweep.timer -= 1;
+        if self.timer > 0 {
+            self.timer -= 1;
         } else {
             self.duty_cycle = (self.duty_cycle + 1) & 7;
-            self.sweep.timer = self.sweep.period;
+            self.timer = self.sweep.period;
         }
     }
 
@@ -384,25 +682,14 @@ impl<const ADDER: u16> Pulse<ADDER> {
 
     /** The mixer receives the current envelope volume except when The sequencer output is zero,
     or overflow from the sweep unit's adder is silencing the channel, or the length counter is
-    zero, or the timer has a value less than eight. **/
+    zero, or the timer period is less than eight. The live countdown doesn't matter, so the
+    channel doesn't flicker in and out. **/
     #[inline]
     fn output(&mut self) -> u8 {
         let active = DUTY_SEQUENCE[(self.duty_seq | self.duty_cycle) as usize];
 
-        if active
-            && self.length_counter.counter > 0
-            && self.sweep.timer >= 8
-            && self.sweep.period < 0x800
-        {
-            /* The envelope unit's volume output depends on the constant volume flag: if set, the
-            envelope parameter directly sets the volume, otherwise the decay level is the current
-            volume. The constant volume flag has no effect besides selecting the volume source;
-            the decay level will still be updated when constant volume is selected. */
-            if self.envelope.constant_volume {
-                return self.envelope.period;
-            } else {
-                return self.envelope.step;
-            }
+        if active && self.length_counter.counter > 0 && !self.sweep.muting() {
+            self.envelope.volume()
         } else {
             0
         }
@@ -420,29 +707,88 @@ impl<const ADDER: u16> Pulse<ADDER> {
 //bits 2-0---- -HHH   Timer high 3 bits
 //Side effects: Sets the linear counter reload flag
 #[derive(Serialize, Deserialize)]
-struct Triangle {
+pub(crate) struct Triangle {
     counter_control: bool,
     counter_reload: u8,
     timer: u16,
+    countdown: u16,
+
+    linear_counter: u8,
+    linear_reload: bool,
+
+    pub(crate) sequence_pos: u8,
 
     length_counter: LengthCounter,
 }
 
+#[rustfmt::skip]
+static TRIANGLE_SEQUENCE: [u8; 0x20] = [
+    15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0,
+    0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15,
+];
+
+/// https://wiki.nesdev.org/w/index.php?title=APU_Triangle
 impl Triangle {
     fn new() -> Triangle {
         Triangle {
             counter_control: false,
             counter_reload: 0,
             timer: 0,
+            countdown: 0,
+
+            linear_counter: 0,
+            linear_reload: false,
+
+            // Start on a step with an output of 0, so a triangle that was never played doesn't
+            // add a DC offset
+            sequence_pos: 16,
 
             length_counter: LengthCounter::new(),
         }
     }
 
+    /// Clocked every CPU cycle
+    #[inline]
+    fn clock(&mut self) {
+        if self.countdown == 0 {
+            self.countdown = self.timer;
+
+            /* The sequencer is clocked only when both the length counter and the linear counter
+            are nonzero, the output level is kept while it's halted.
+            Periods below 2 would produce ultrasonic frequencies, real hardware outputs their
+            average value, games use them to silence the channel, so freeze the sequencer instead
+            to avoid pops. */
+            if self.length_counter.counter > 0 && self.linear_counter > 0 && self.timer >= 2 {
+                self.sequence_pos = (self.sequence_pos + 1) & 0x1F;
+            }
+        } else {
+            self.countdown -= 1;
+        }
+    }
+
+    /// Clocked by the frame counter's quarter frames
+    #[inline]
+    fn linear_clock(&mut self) {
+        if self.linear_reload {
+            self.linear_counter = self.counter_reload;
+        } else if self.linear_counter > 0 {
+            self.linear_counter -= 1;
+        }
+
+        if !self.counter_control {
+            self.linear_reload = false;
+        }
+    }
+
+    #[inline]
+    fn output(&self) -> u8 {
+        TRIANGLE_SEQUENCE[self.sequence_pos as usize]
+    }
+
     #[inline]
     fn set_c(&mut self, val: u8) {
         self.counter_control = val & 0x80 != 0;
-        self.length_counter.enabled = val & 0x80 == 0;
+        self.length_counter.set_halt(val & 0x80 != 0);
         self.counter_reload = val & 0x7F;
     }
 
@@ -455,7 +801,7 @@ impl Triangle {
     fn set_l(&mut self, val: u8) {
         self.length_counter.load((val & 0xF8) >> 3);
         self.timer = (self.timer & !0x700) | (u16::from(val & 7) << 8);
-        //TODO: set linear control reload flag
+        self.linear_reload = true;
     }
 }
 
@@ -492,7 +838,7 @@ impl Noise {
 
     #[inline]
     fn set_lcn(&mut self, val: u8) {
-        self.length_counter.enabled = (val & 0x20) == 0;
+        self.length_counter.set_halt(val & 0x20 != 0);
         self.constant_volume = (val & 0x10) != 0;
         self.volume = val & 0xF;
     }
@@ -509,47 +855,77 @@ impl Noise {
     }
 }
 
+static DMC_RATE: [u16; 0x10] = [
+    428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54,
+];
+
 //$4010   IL-- FFFF   IRQ enable, loop sample, frequency index
 //$4011   -DDD DDDD   Direct load
 //$4012   AAAA AAAA   Sample address %11AAAAAA.AA000000
 //$4013   LLLL LLLL   Sample length %0000LLLL.LLLL0001
+/// https://wiki.nesdev.org/w/index.php?title=APU_DMC
 #[derive(Serialize, Deserialize)]
-struct Dmc {
+pub(crate) struct Dmc {
     irq_enable: bool,
+    /// Set when a sample ends with the IRQ enabled, reported in $4015 bit 7
+    irq_flag: bool,
     loop_sample: bool,
     frequency_index: u8,
 
-    direct_load: u8,
+    /// Timer period in CPU cycles
+    rate: u16,
+    timer: u16,
 
-    sample_address: u16,
+    output_level: u8,
+    shift_register: u8,
+    bits_remaining: u8,
+    silence: bool,
 
+    sample_address: u16,
     sample_length: u16,
+
+    sample_buffer: Option<u8>,
+    current_address: u16,
+    pub(crate) bytes_remaining: u16,
 }
 
 impl Dmc {
     fn new() -> Dmc {
         Dmc {
             irq_enable: false,
+            irq_flag: false,
             loop_sample: false,
             frequency_index: 0,
 
-            direct_load: 0,
-            sample_address: 0,
+            rate: DMC_RATE[0],
+            timer: DMC_RATE[0],
+
+            output_level: 0,
+            shift_register: 0,
+            bits_remaining: 8,
+            silence: true,
 
-            sample_length: 0,
+            sample_address: 0xC000,
+            sample_length: 1,
+
+            sample_buffer: None,
+            current_address: 0xC000,
+            bytes_remaining: 0,
         }
     }
 
     #[inline]
     fn set_ilf(&mut self, val: u8) {
         self.irq_enable = (val & 0x80) != 0;
+        self.irq_flag = false;
         self.loop_sample = (val & 0x40) != 0;
         self.frequency_index = val & 0xF;
+        self.rate = DMC_RATE[self.frequency_index as usize];
     }
 
     #[inline]
     fn set_d(&mut self, val: u8) {
-        self.direct_load = val & 0x7F;
+        self.output_level = val & 0x7F;
     }
 
     #[inline]
@@ -561,6 +937,64 @@ impl Dmc {
     fn set_l(&mut self, val: u8) {
         self.sample_length = 1 | (u16::from(val) << 4);
     }
+
+    #[inline]
+    fn restart(&mut self) {
+        self.current_address = self.sample_address;
+        self.bytes_remaining = self.sample_length;
+    }
+
+    /// The memory reader got the next sample byte from the DMA
+    #[inline]
+    fn fill(&mut self, val: u8) {
+        self.sample_buffer = Some(val);
+        self.current_address = match self.current_address {
+            0xFFFF => 0x8000,
+            addr => addr + 1,
+        };
+
+        self.bytes_remaining -= 1;
+        if self.bytes_remaining == 0 {
+            if self.loop_sample {
+                self.restart();
+            } else if self.irq_enable {
+                self.irq_flag = true;
+            }
+        }
+    }
+
+    /// The timer is clocked every CPU cycle, the rates are in CPU cycles as well
+    #[inline]
+    fn clock(&mut self) {
+        if self.timer > 1 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.rate;
+
+        if !self.silence {
+            if self.shift_register & 1 != 0 {
+                if self.output_level <= 125 {
+                    self.output_level += 2;
+                }
+            } else if self.output_level >= 2 {
+                self.output_level -= 2;
+            }
+        }
+        self.shift_register >>= 1;
+
+        self.bits_remaining -= 1;
+        if self.bits_remaining == 0 {
+            self.bits_remaining = 8;
+            match self.sample_buffer.take() {
+                Some(sample) => {
+                    self.silence = false;
+                    self.shift_register = sample;
+                }
+                None => self.silence = true,
+            }
+        }
+    }
 }
 
 //$4017   MI--.----   Set mode and interrupt (write)
@@ -574,6 +1008,10 @@ struct FrameCounter {
     mode: bool, //true -5-step, false-4-step
     odd_cycle: bool,
     irq_inhibit: bool,
+    /// Stays set until it's acknowledged by reading $4015 or inhibited by writing $4017
+    irq_flag: bool,
+    /// CPU cycles until a $4017 write resets the sequence, 0 if there's no pending reset
+    reset_delay: u8,
 }
 
 impl FrameCounter {
@@ -581,22 +1019,63 @@ impl FrameCounter {
         FrameCounter {
             mode: false,
             odd_cycle: false,
-            irq_inhibit: true,
+            irq_inhibit: false,
+            irq_flag: false,
+            reset_delay: 0,
         }
     }
 
     #[inline]
     /// https://wiki.nesdev.org/w/index.php?title=APU_Frame_Counter
-    fn set_mi(&mut self, val: u8, irq_signal: &mut bool) {
+    fn set_mi(&mut self, val: u8) {
         self.mode = val & 0x80 != 0;
-        self.irq_inhibit &= val & 0x40 == 0;
+        self.irq_inhibit = val & 0x40 != 0;
 
         if self.irq_inhibit {
-            *irq_signal = false;
+            self.irq_flag = false;
+        }
+    }
+
+    #[inline]
+    fn raise_irq(&mut self) {
+        if !self.irq_inhibit {
+            self.irq_flag = true;
         }
     }
 }
 
+/// Number of windows in an audio fingerprint, one nibble each
+const FINGERPRINT_WINDOWS: usize = 16;
+/// RMS step between two fingerprint levels
+const FINGERPRINT_LEVEL: f32 = 1. / 64.;
+
+/// The RMS (without the DC offset) of 16 equally long windows of `samples`, quantized to 4 bits
+pub(crate) fn audio_fingerprint(samples: &[f32]) -> u64 {
+    let window_len = samples.len() / FINGERPRINT_WINDOWS;
+    if window_len == 0 {
+        return 0;
+    }
+
+    samples
+        .chunks_exact(window_len)
+        .take(FINGERPRINT_WINDOWS)
+        .enumerate()
+        .fold(0, |fingerprint, (i, window)| {
+            let mean = window.iter().sum::<f32>() / window_len as f32;
+            let power = window.iter().map(|s| (s - mean).powi(2)).sum::<f32>() / window_len as f32;
+            let level = (power.sqrt() / FINGERPRINT_LEVEL).round().min(15.) as u64;
+            fingerprint | (level << (i * 4))
+        })
+}
+
+/// Levels on the edge of a quantization step can round either way
+pub(crate) fn audio_fingerprints_match(a: u64, b: u64) -> bool {
+    (0..FINGERPRINT_WINDOWS).all(|i| {
+        let (a, b) = ((a >> (i * 4)) & 0xF, (b >> (i * 4)) & 0xF);
+        a.abs_diff(b) <= 1
+    })
+}
+
 static LENGTH_TABLE: [u8; 0x20] = [
     10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14, 12, 16, 24, 18, 48, 20, 96, 22,
     192, 24, 72, 26, 16, 28, 32, 30,
@@ -604,44 +1083,88 @@ static LENGTH_TABLE: [u8; 0x20] = [
 
 #[derive(Serialize, Deserialize)]
 struct LengthCounter {
+    /// Channel enable bit of $4015, the counter stays at 0 while it is clear
     enabled: bool,
+    halt: bool,
     counter: u8,
+
+    /// Halt flag and reload written during the current cycle, they take effect after the
+    /// frame counter has clocked the length counter
+    new_halt: bool,
+    reload: Option<u8>,
+    /// The counter value when the reload was written
+    reload_counter: u8,
 }
 
 impl LengthCounter {
     fn new() -> LengthCounter {
         LengthCounter {
-            enabled: true,
+            enabled: false,
+            halt: false,
             counter: 0,
+
+            new_halt: false,
+            reload: None,
+            reload_counter: 0,
         }
     }
 
     #[inline]
     fn load(&mut self, val: u8) {
         if self.enabled {
-            self.counter = LENGTH_TABLE[val as usize];
+            self.reload = Some(LENGTH_TABLE[val as usize]);
+            self.reload_counter = self.counter;
+        }
+    }
+
+    #[inline]
+    fn set_halt(&mut self, halt: bool) {
+        self.new_halt = halt;
+    }
+
+    #[inline]
+    fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.counter = 0;
+            self.reload = None;
         }
     }
 
     #[inline]
     fn clock(&mut self) {
-        if self.counter > 0 && self.enabled {
+        if self.counter > 0 && !self.halt {
             self.counter -= 1;
         }
     }
+
+    /// A reload is ignored if the counter has been clocked from a non-zero value on the same
+    /// cycle
+    #[inline]
+    fn apply_writes(&mut self) {
+        if let Some(reload) = self.reload.take() {
+            if self.counter == self.reload_counter {
+                self.counter = reload;
+            }
+        }
+        self.halt = self.new_halt;
+    }
 }
 
+/// `ADDER` is the extra amount subtracted by a negated change: 1 on pulse 1 (ones' complement),
+/// 0 on pulse 2 (two's complement)
 #[derive(Serialize, Deserialize)]
 struct Sweep<const ADDER: u16> {
     enabled: bool,
     negate: bool,
     shift: u8,
 
-    period: u16,
+    divider_period: u16,
     counter: u16,
 
     reload: bool,
-    timer: u16,
+    /// Timer period of the pulse channel, adjusted by the sweeps
+    period: u16,
 }
 
 impl<const ADDER: u16> Sweep<ADDER> {
@@ -651,18 +1174,18 @@ impl<const ADDER: u16> Sweep<ADDER> {
             negate: false,
             shift: 0,
 
-            period: 0,
+            divider_period: 0,
             counter: 0,
 
             reload: false,
-            timer: 0,
+            period: 0,
         }
     }
 
     #[inline]
     fn load(&mut self, val: u8) {
         self.enabled = (val & 0x80) != 0;
-        self.period = (val as u16 & 0x70) >> 4;
+        self.divider_period = (val as u16 & 0x70) >> 4;
         self.negate = (val & 8) != 0;
         self.shift = val & 7;
         self.reload = true;
@@ -677,14 +1200,14 @@ impl<const ADDER: u16> Sweep<ADDER> {
     #[inline]
     fn clock(&mut self) {
         if self.counter == 0 || self.reload {
-            self.counter = self.period + 1;
+            self.counter = self.divider_period + 1;
             self.reload = false;
         } else {
             self.counter -= 1;
         }
 
-        let mute = false;
-        if self.counter == 0 && self.enabled && !mute {
+        // The target period is calculated continuously, but only applied with a shift count
+        if self.counter == 0 && self.enabled && self.shift > 0 && !self.muting() {
             //The sweep unit continuously calculates each channel's target period in this way:
 
             //A barrel shifter shifts the channel's 11-bit raw timer period right by the shift count,
@@ -700,16 +1223,28 @@ impl<const ADDER: u16> Sweep<ADDER> {
             //Pulse 1 adds the ones' complement (−c − 1). Making 20 negative produces a change amount
             //of −21.
             //Pulse 2 adds the two's complement (−c). Making 20 negative produces a change amount of −20.
-            self.counter = self.period + 1;
+            self.counter = self.divider_period + 1;
+            self.period = self.target_period();
+        }
+    }
 
-            let change = self.timer >> self.shift;
-            if !self.negate {
-                self.period += change;
-            } else {
-                self.period += ADDER - change;
-            }
+    #[inline]
+    fn target_period(&self) -> u16 {
+        let change = self.period >> self.shift;
+        if self.negate {
+            // The adder is 11 bits wide, negative results wrap around
+            self.period.wrapping_sub(change + ADDER) & 0x7FF
+        } else {
+            self.period + change
         }
     }
+
+    /// Mutes the channel even while the sweep is disabled, a target period above $7FF is never
+    /// applied
+    #[inline]
+    fn muting(&self) -> bool {
+        self.period < 8 || self.target_period() > 0x7FF
+    }
 }
 
 #[derive(Serialize, Deserialize)]
@@ -734,6 +1269,19 @@ impl Envelope {
         }
     }
 
+    /* The envelope unit's volume output depends on the constant volume flag: if set, the
+    envelope parameter directly sets the volume, otherwise the decay level is the current
+    volume. The constant volume flag has no effect besides selecting the volume source;
+    the decay level will still be updated when constant volume is selected. */
+    #[inline]
+    fn volume(&self) -> u8 {
+        if self.constant_volume {
+            self.period
+        } else {
+            self.step
+        }
+    }
+
     //When clocked by the frame counter, one of two actions occurs: if the start flag is clear, the divider
     //is clocked, otherwise the start flag is cleared, the decay level counter is loaded with 15, and the
     //divider's period is immediately reloaded.
@@ -0,0 +1,115 @@
+//! Runs a ROM without any window or audio output, for CI and scripting.
+//!
+//! Usage: headless <ROM> [--frames N] [--until ADDR=VAL] [--screenshot OUT.ppm]
+//!
+//! `--until` stops as soon as the byte at the CPU address ADDR (hex) is equal to VAL (hex),
+//! the process then exits with 0, or with 1 if the frame limit has been reached first.
+//! The text output of blargg-style test ROMs ($6004) is printed if present.
+
+use std::{env, path::PathBuf, process};
+
+use fearless_nes::NesBuilder;
+
+struct Args {
+    rom: PathBuf,
+    frames: u64,
+    until: Option<(u16, u8)>,
+    screenshot: Option<PathBuf>,
+}
+
+const USAGE: &str = "usage: headless <ROM> [--frames N] [--until ADDR=VAL] [--screenshot OUT.ppm]";
+
+fn parse_args() -> Result<Args, String> {
+    let mut args = env::args().skip(1);
+
+    let mut rom = None;
+    let mut frames = 600;
+    let mut until = None;
+    let mut screenshot = None;
+
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("missing value for {}", arg));
+
+        match arg.as_str() {
+            "--frames" => {
+                frames = value()?
+                    .parse()
+                    .map_err(|_| "--frames expects a number".to_string())?
+            }
+            "--until" => {
+                let condition = value()?;
+                let (addr, val) = condition
+                    .split_once('=')
+                    .ok_or("--until expects ADDR=VAL")?;
+                let addr = u16::from_str_radix(addr.trim_start_matches('$'), 16)
+                    .map_err(|_| "invalid --until address")?;
+                let val = u8::from_str_radix(val.trim_start_matches('$'), 16)
+                    .map_err(|_| "invalid --until value")?;
+                until = Some((addr, val));
+            }
+            "--screenshot" => screenshot = Some(PathBuf::from(value()?)),
+            "-h" | "--help" => return Err(USAGE.to_string()),
+            _ if rom.is_none() && !arg.starts_with("--") => rom = Some(PathBuf::from(arg)),
+            _ => return Err(format!("unexpected argument: {}\n{}", arg, USAGE)),
+        }
+    }
+
+    Ok(Args {
+        rom: rom.ok_or(USAGE)?,
+        frames,
+        until,
+        screenshot,
+    })
+}
+
+fn main() {
+    let args = parse_args().unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        process::exit(2);
+    });
+
+    let mut nes = NesBuilder::new()
+        .build_from_path(&args.rom)
+        .unwrap_or_else(|err| {
+            eprintln!("error: {}", err);
+            process::exit(2);
+        });
+
+    let condition_met = match args.until {
+        Some((addr, val)) => nes.run_until(args.frames, |nes| nes.peek(addr) == val),
+        None => {
+            nes.run_until(args.frames, |_| false);
+            true
+        }
+    };
+
+    // Test ROMs write a signature to $6001 - $6003 and a zero-terminated string to $6004
+    if (0x6001..=0x6003)
+        .map(|addr| nes.peek(addr))
+        .eq([0xDE, 0xB0, 0x61])
+    {
+        let text: String = (0x6004..=0x7FFF)
+            .map(|addr| nes.peek(addr))
+            .take_while(|&byte| byte != 0)
+            .map(char::from)
+            .collect();
+        println!("{}", text);
+    }
+
+    if let Some(path) = args.screenshot {
+        if let Err(err) = nes.save_screenshot(&path) {
+            eprintln!("couldn't save the screenshot: {}", err);
+            process::exit(2);
+        }
+    }
+
+    println!(
+        "ran {} frames ({} CPU cycles)",
+        nes.get_frame_count(),
+        nes.get_cycle_count()
+    );
+
+    if !condition_met {
+        process::exit(1);
+    }
+}
@@ -0,0 +1,188 @@
+use std::{
+    collections::VecDeque,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+    apu::Apu, cartridge::Cartridge, cartridge::Region, controller::Controller, cpu::Cpu,
+    mapper::BaseMapper, patch, ppu::Ppu, Nes, NesError,
+};
+
+/// Contents of the CPU RAM at power-on.
+/// The real hardware state is indeterminate, some games rely on a particular pattern.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum RamInit {
+    Zeroes,
+    Fill(u8),
+    /// Pseudo-random bytes generated from the seed
+    Random(u64),
+}
+
+impl RamInit {
+    pub(crate) fn fill(self, ram: &mut [u8]) {
+        match self {
+            RamInit::Zeroes => ram.fill(0),
+            RamInit::Fill(val) => ram.fill(val),
+            RamInit::Random(seed) => {
+                // xorshift64*, the state must not be 0
+                let mut state = seed | 1;
+                for byte in ram.iter_mut() {
+                    state ^= state >> 12;
+                    state ^= state << 25;
+                    state ^= state >> 27;
+                    *byte = (state.wrapping_mul(0x2545F4914F6CDD1D) >> 56) as u8;
+                }
+            }
+        }
+    }
+}
+
+/// Construction options for [`Nes`], [`Nes::new`] uses the defaults
+#[derive(Clone, Serialize, Deserialize)]
+pub struct NesBuilder {
+    region: Option<Region>,
+    ram_init: RamInit,
+    mapper_override: Option<u32>,
+    patch: Option<PathBuf>,
+    sample_rate: Option<u32>,
+    rendering: bool,
+}
+
+impl Default for NesBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl NesBuilder {
+    pub fn new() -> Self {
+        Self {
+            region: None,
+            ram_init: RamInit::Zeroes,
+            mapper_override: None,
+            patch: None,
+            sample_rate: None,
+            rendering: true,
+        }
+    }
+
+    /// Overrides the region from the ROM header. Only NTSC timing is emulated, building for PAL or
+    /// Dendy fails with [`NesError::RegionUnsupported`].
+    pub fn region(mut self, region: Region) -> Self {
+        self.region = Some(region);
+        self
+    }
+
+    /// Defaults to [`RamInit::Zeroes`]
+    pub fn ram_init(mut self, ram_init: RamInit) -> Self {
+        self.ram_init = ram_init;
+        self
+    }
+
+    /// Overrides the mapper number from the ROM header (or the game database), useful for ROMs
+    /// with bad headers
+    pub fn mapper_override(mut self, mapper: u32) -> Self {
+        self.mapper_override = Some(mapper);
+        self
+    }
+
+    /// IPS or BPS patch file, applied to the ROM file before its header is parsed. BPS patches
+    /// made for another ROM fail with [`NesError::PatchMismatch`].
+    pub fn patch(mut self, path: &Path) -> Self {
+        self.patch = Some(path.to_path_buf());
+        self
+    }
+
+    /// Audio output sample rate in Hz, by default a sample is output every 40 CPU cycles
+    /// (about 44.7 kHz)
+    pub fn sample_rate(mut self, sample_rate: u32) -> Self {
+        self.sample_rate = Some(sample_rate.max(1));
+        self
+    }
+
+    /// When false, the PPU still runs (sprite 0 hits, NMIs...) but the frame buffer stays untouched
+    pub fn rendering(mut self, rendering: bool) -> Self {
+        self.rendering = rendering;
+        self
+    }
+
+    pub fn build_from_path(self, path: &Path) -> Result<Nes, NesError> {
+        let rom = fs::read(path)?;
+        self.build_from_bytes(&rom)
+    }
+
+    /// Same options for another cartridge, the mapper override and the patch only apply to the
+    /// first one
+    pub(crate) fn for_next_cartridge(&self) -> NesBuilder {
+        NesBuilder {
+            mapper_override: None,
+            patch: None,
+            ..self.clone()
+        }
+    }
+
+    /// The CPU (with the RAM filled according to [`NesBuilder::ram_init`]), the PPU and the APU
+    /// in their power-on state
+    pub(crate) fn power_on_chips(&self, region: Region) -> (Cpu, Ppu, Apu) {
+        let mut cpu = Cpu::new();
+        self.ram_init.fill(&mut cpu.ram);
+
+        let mut ppu = Ppu::new();
+        ppu.output_enabled = self.rendering;
+
+        (cpu, ppu, Apu::new(region, self.sample_rate))
+    }
+
+    pub fn build_from_bytes(self, rom: &[u8]) -> Result<Nes, NesError> {
+        let mut cartridge = match &self.patch {
+            Some(path) => Cartridge::from_rom(&patch::apply_patch(rom, &fs::read(path)?)?)?,
+            None => Cartridge::from_rom(rom)?,
+        };
+        if let Some(mapper) = self.mapper_override {
+            cartridge.header.mapper = mapper;
+        }
+
+        let region = match self.region.unwrap_or(cartridge.header.region) {
+            Region::Ntsc | Region::Multi => Region::Ntsc,
+            region => return Err(NesError::RegionUnsupported(region)),
+        };
+
+        let (cpu, ppu, apu) = self.power_on_chips(region);
+
+        let mut nes = Nes {
+            cpu,
+            ppu,
+            apu,
+
+            mapper: BaseMapper::new(cartridge)?,
+
+            controller: Controller::new(),
+            input_script: VecDeque::new(),
+
+            region,
+            overclock_scanlines: 0,
+            overclock_cycles: 0,
+            overclocked_total: 0,
+            ppu_dots_ahead: 0,
+
+            boot_hook: None,
+            breakpoints: Vec::new(),
+            unimplemented_write_hook: None,
+            mapper_write_callback: None,
+
+            frame_ready: false,
+            cycle_count: 0,
+            cycle_offset: 0,
+
+            frame_count: 0,
+
+            options: self,
+        };
+
+        nes.cpu_gen_reset();
+        Ok(nes)
+    }
+}
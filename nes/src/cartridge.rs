@@ -1,8 +1,16 @@
-use std::{convert::TryFrom, fmt::Display};
+use std::{
+    convert::TryFrom,
+    fmt::Display,
+    fs::{self, File},
+    hash::Hasher,
+    io::Read,
+    path::{Path, PathBuf},
+};
 
 use crate::{ppu::Mirroring, NesError};
 
 use serde::{Deserialize, Serialize};
+use siphasher::sip::SipHasher13;
 
 mod gamedb;
 
@@ -14,17 +22,15 @@ pub struct Cartridge {
     prg_rom: Vec<u8>,
     prg_wram: Option<Vec<u8>>,
     chr: Vec<u8>,
+    /// SipHash 1-3 of PRG ROM and CHR ROM, identifies the game of a save state
+    hash: u64,
 }
 
 impl Cartridge {
     /// First parse the iNES header, then try to find information in the NEs 2.0 XML Game Database
     pub fn from_rom(rom: &[u8]) -> Result<Cartridge, NesError> {
-        if rom.len() < HEADER_SIZE {
-            return Err(NesError::InvalidInesFormat);
-        }
-        let (header, rom) = rom.split_at(HEADER_SIZE);
-
-        let header = Header::from_ines(header)?;
+        let header = Header::from_rom(rom)?;
+        let rom = &rom[HEADER_SIZE..];
 
         let prg_end = header.prg_rom_size as usize;
         let prg_portion = rom.get(0..prg_end).ok_or(NesError::RomCorrupted)?;
@@ -58,9 +64,11 @@ impl Cartridge {
             return Err(NesError::ConsoleUnsupported(header.console_typ));
         };
 
-        if header.region != Region::Ntsc && header.region != Region::Multi {
-            return Err(NesError::RegionUnsupported(header.region));
-        };
+        let mut hasher = SipHasher13::new();
+        hasher.write(&prg_rom);
+        if header.chr_rom_size.is_some() {
+            hasher.write(&chr);
+        }
 
         Ok(Cartridge {
             header,
@@ -68,9 +76,23 @@ impl Cartridge {
             prg_rom,
             prg_wram,
             chr,
+            hash: hasher.finish(),
         })
     }
 
+    pub fn hash(&self) -> u64 {
+        self.hash
+    }
+
+    pub fn prg_rom(&self) -> &[u8] {
+        &self.prg_rom
+    }
+
+    /// CHR ROM, or the current contents of CHR RAM
+    pub fn chr(&self) -> &[u8] {
+        &self.chr
+    }
+
     /// Banks are indexed from 0
     pub fn map_bank(bank: u8, bank_size: BankSize) -> usize {
         bank as usize * bank_size as usize
@@ -147,6 +169,47 @@ impl Cartridge {
     }
 }
 
+/// A ROM file found while scanning a directory
+pub struct RomEntry {
+    pub path: PathBuf,
+    /// The parsed iNES header, or the reason why the file couldn't be parsed
+    pub header: Result<Header, NesError>,
+}
+
+/// Only reads the iNES header of each `.nes` file, other files and subdirectories are skipped.
+/// Entries are sorted by path.
+pub(crate) fn scan_directory(dir: &Path) -> Vec<RomEntry> {
+    let mut entries: Vec<RomEntry> = match fs::read_dir(dir) {
+        Ok(dir) => dir
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.is_file() && is_rom_file(path))
+            .map(|path| RomEntry {
+                header: read_header(&path),
+                path,
+            })
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+    entries
+}
+
+fn is_rom_file(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("nes"))
+}
+
+fn read_header(path: &Path) -> Result<Header, NesError> {
+    let mut header = [0; HEADER_SIZE];
+    File::open(path)?
+        .read_exact(&mut header)
+        .map_err(|_| NesError::InvalidInesFormat)?;
+
+    Header::from_rom(&header)
+}
+
 pub enum BankSize {
     Kb1 = 0x400,
     #[allow(dead_code)]
@@ -192,6 +255,15 @@ const NES_CONSTANT: [u8; 4] = [0x4E, 0x45, 0x53, 0x1A];
 
 /** https://wiki.nesdev.org/w/index.php?title=INES **/
 impl Header {
+    /// Parses the header at the start of an iNES file, the rest of the file is ignored
+    pub fn from_rom(rom: &[u8]) -> Result<Self, NesError> {
+        if rom.len() < HEADER_SIZE {
+            return Err(NesError::InvalidInesFormat);
+        }
+
+        Header::from_ines(&rom[..HEADER_SIZE])
+    }
+
     pub fn from_ines(ines: &[u8]) -> Result<Self, NesError> {
         if ines[0..=3] != NES_CONSTANT {
             return Err(NesError::InvalidInesFormat);
@@ -213,7 +285,7 @@ impl Header {
         let mapper = u32::from((ines[6] >> 4) | (ines[7] & 0xF0));
 
         if ines[7] & 0xC == 0x8 {
-            return Err(NesError::Ines2Unsupported);
+            return Header::from_ines2(ines, mirroring, battery, mapper);
         }
 
         let (chr_rom_size, chr_ram_size) = match ines[5] {
@@ -251,6 +323,79 @@ impl Header {
             expansion: 1,
         })
     }
+
+    /// The NES 2.0 extension of the iNES format, the fields of flags 6 are parsed by the caller
+    fn from_ines2(
+        ines: &[u8],
+        mirroring: Mirroring,
+        battery: bool,
+        mapper: u32,
+    ) -> Result<Self, NesError> {
+        let mapper = mapper | (u32::from(ines[8] & 0xF) << 8);
+
+        let prg_rom_size = Self::ines2_rom_size(ines[4], ines[9] & 0xF, BankSize::Kb16);
+        let chr_rom_size = match Self::ines2_rom_size(ines[5], ines[9] >> 4, BankSize::Kb8) {
+            0 => None,
+            size => Some(size),
+        };
+
+        // CHR RAM is assumed by old headers that don't specify any CHR memory
+        let chr_ram_size = match (Self::ines2_ram_size(ines[11] & 0xF), chr_rom_size) {
+            (None, None) => Some(BankSize::Kb8 as u32),
+            (size, _) => size,
+        };
+
+        let console_typ = match ines[7] & 3 {
+            0 => ConsoleType::Standard,
+            1 => ConsoleType::VsSystem,
+            2 => ConsoleType::Playchoice,
+            3 => ConsoleType::Extended,
+            _ => unreachable!(),
+        };
+
+        Ok(Header {
+            mirroring,
+            source: HeaderSource::Ines2,
+            name: String::from(""),
+            prg_rom_size,
+            chr_rom_size,
+            chr_ram_size,
+            prg_ram_size: Self::ines2_ram_size(ines[10] & 0xF),
+            prg_nvram_size: Self::ines2_ram_size(ines[10] >> 4),
+            mapper,
+            submapper: u32::from(ines[8] >> 4),
+            battery,
+            console_typ,
+            region: match ines[12] & 3 {
+                0 => Region::Ntsc,
+                1 => Region::Pal,
+                2 => Region::Multi,
+                3 => Region::Dendy,
+                _ => unreachable!(),
+            },
+            expansion: u32::from(ines[15] & 0x3F),
+        })
+    }
+
+    /// ROM sizes are either a count of `unit` banks, or `2^E * (MM * 2 + 1)` bytes written as
+    /// EEEEEEMM in the LSB byte if the MSB nibble is $F
+    fn ines2_rom_size(lsb: u8, msb: u8, unit: BankSize) -> u32 {
+        if msb == 0xF {
+            let size = (1u128 << (lsb >> 2)) * (u128::from(lsb & 3) * 2 + 1);
+            u32::try_from(size).unwrap_or(u32::MAX)
+        } else {
+            ((u32::from(msb) << 8) | u32::from(lsb)) * unit as u32
+        }
+    }
+
+    /// RAM sizes are shift counts, `64 << shift` bytes or none if the shift count is 0
+    fn ines2_ram_size(shift: u8) -> Option<u32> {
+        match shift {
+            0 => None,
+            // Shift counts above 14 are reserved
+            shift => Some(64 << shift.min(14)),
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize)]
@@ -304,14 +449,60 @@ impl TryFrom<&str> for ConsoleType {
     }
 }
 
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub enum Region {
     Ntsc = 0,
     Pal = 1,
+    /// Runs as NTSC
     Multi = 2,
     Dendy = 3,
 }
 
+/// https://wiki.nesdev.org/w/index.php?title=Cycle_reference_chart
+impl Region {
+    /// CPU clock rate in Hz
+    pub fn cpu_clock_rate(self) -> f64 {
+        match self {
+            Region::Ntsc | Region::Multi => 236.25e6 / 11. / 12.,
+            Region::Pal => 26.601712e6 / 16.,
+            Region::Dendy => 26.601712e6 / 15.,
+        }
+    }
+
+    /// Master clock cycles per CPU cycle. The PPU divides the master clock by 4 (NTSC, Dendy)
+    /// or by 5 (PAL), so there are 3 or 3.2 PPU dots per CPU cycle
+    pub fn master_clock_divider(self) -> u64 {
+        match self {
+            Region::Ntsc | Region::Multi => 12,
+            Region::Pal => 16,
+            Region::Dendy => 15,
+        }
+    }
+
+    /// Master clock cycles per PPU dot
+    pub(crate) fn ppu_clock_divider(self) -> u64 {
+        match self {
+            Region::Ntsc | Region::Multi | Region::Dendy => 4,
+            Region::Pal => 5,
+        }
+    }
+
+    /// Average number of CPU cycles per frame (NTSC frames alternate between 29780 and 29781 cycles
+    /// when rendering is enabled)
+    pub fn cpu_cycles_per_frame(self) -> f64 {
+        match self {
+            Region::Ntsc | Region::Multi => 341. * 262. / 3. - 0.5 / 3.,
+            Region::Pal => 341. * 312. / 3.2,
+            Region::Dendy => 341. * 312. / 3.,
+        }
+    }
+
+    /// Frames per second
+    pub fn frame_rate(self) -> f64 {
+        self.cpu_clock_rate() / self.cpu_cycles_per_frame()
+    }
+}
+
 impl Display for Region {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -1,3 +1,5 @@
+use std::collections::VecDeque;
+
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize)]
@@ -5,6 +7,22 @@ pub struct Controller {
     strobe: bool,
     shifter: u8,
     state: u8,
+
+    /// Disabled when the capacity is 0
+    #[serde(skip)]
+    read_log: VecDeque<ControllerRead>,
+    #[serde(skip)]
+    read_log_capacity: usize,
+}
+
+/// A CPU read of one of the controller ports
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ControllerRead {
+    pub frame: u64,
+    /// $4016 or $4017
+    pub addr: u16,
+    /// The whole byte the CPU has read, including the open bus bits
+    pub value: u8,
 }
 
 impl Controller {
@@ -13,7 +31,34 @@ impl Controller {
             strobe: false,
             shifter: 0,
             state: 0,
+
+            read_log: VecDeque::new(),
+            read_log_capacity: 0,
+        }
+    }
+
+    /// The oldest reads get dropped once `capacity` reads are logged, 0 disables the log
+    pub(crate) fn set_read_log_capacity(&mut self, capacity: usize) {
+        self.read_log_capacity = capacity;
+        self.read_log.truncate(capacity);
+        self.read_log.shrink_to_fit();
+    }
+
+    pub(crate) fn read_log(&self) -> &VecDeque<ControllerRead> {
+        &self.read_log
+    }
+
+    #[inline]
+    pub(crate) fn log_read(&mut self, frame: u64, addr: u16, value: u8) {
+        if self.read_log_capacity == 0 {
+            return;
+        }
+
+        if self.read_log.len() == self.read_log_capacity {
+            self.read_log.pop_front();
         }
+        self.read_log
+            .push_back(ControllerRead { frame, addr, value });
     }
 
     #[inline]
@@ -39,16 +84,34 @@ impl Controller {
 
     #[inline]
     pub fn set_button(&mut self, keycode: Button, state: bool) {
-        self.state = match keycode {
-            Button::A => (self.state & !1) | (state as u8),
-            Button::B => (self.state & !(1 << 1)) | (state as u8) << 1,
-            Button::Select => (self.state & !(1 << 2)) | (state as u8) << 2,
-            Button::Start => (self.state & !(1 << 3)) | (state as u8) << 3,
-            Button::Up => (self.state & !(1 << 4)) | (state as u8) << 4,
-            Button::Down => (self.state & !(1 << 5)) | (state as u8) << 5,
-            Button::Left => (self.state & !(1 << 6)) | (state as u8) << 6,
-            Button::Right => (self.state & !(1 << 7)) | (state as u8) << 7,
-        }
+        self.state = (self.state & !keycode.mask()) | if state { keycode.mask() } else { 0 };
+    }
+
+    pub(crate) fn set_buttons(&mut self, buttons: ControllerButtons) {
+        self.state = buttons.0;
+    }
+
+    pub(crate) fn buttons(&self) -> ControllerButtons {
+        ControllerButtons(self.state)
+    }
+}
+
+/// The state of every button of a controller, one bit per button in the order of the
+/// controller shift register (A is bit 0, Right is bit 7)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct ControllerButtons(pub u8);
+
+impl ControllerButtons {
+    pub fn pressed(buttons: &[Button]) -> Self {
+        Self(
+            buttons
+                .iter()
+                .fold(0, |state, button| state | button.mask()),
+        )
+    }
+
+    pub fn is_pressed(&self, button: Button) -> bool {
+        self.0 & button.mask() != 0
     }
 }
 
@@ -63,3 +126,18 @@ pub enum Button {
     Left,
     Right,
 }
+
+impl Button {
+    fn mask(&self) -> u8 {
+        match self {
+            Button::A => 1,
+            Button::B => 1 << 1,
+            Button::Select => 1 << 2,
+            Button::Start => 1 << 3,
+            Button::Up => 1 << 4,
+            Button::Down => 1 << 5,
+            Button::Left => 1 << 6,
+            Button::Right => 1 << 7,
+        }
+    }
+}
@@ -1,6 +1,6 @@
 use serde::{Deserialize, Serialize};
 
-use super::Nes;
+use super::{snapshot::CpuSnapshot, IrqSource, Nes};
 
 #[derive(Serialize, Deserialize)]
 enum InterruptType {
@@ -17,6 +17,23 @@ enum DmaHijack {
     None,
 }
 
+/// Executions and total CPU cycles of each opcode, see [`Nes::enable_opcode_profiling`].
+/// Interrupts count as BRK ($00), OAM DMA and jammed cycles aren't counted.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OpcodeProfile {
+    pub executions: [u64; 256],
+    pub cycles: [u64; 256],
+}
+
+impl OpcodeProfile {
+    fn new() -> Self {
+        Self {
+            executions: [0; 256],
+            cycles: [0; 256],
+        }
+    }
+}
+
 /**
     Most of the documentation for the 6502 can be found on nesdev:
     http://nesdev.org/6502_cpu.txt
@@ -57,21 +74,31 @@ pub struct Cpu {
     pub open_bus: u8,
 
     pub irq_signal: bool,
+    /// IRQ line that's only asserted through [`Nes::assert_irq`]
+    external_irq: bool,
     pub nmi_signal: bool,
     /// status of the IRQ line sampled at the end of the penultimate cycle of an instruction
     cached_irq: bool,
-    /// status of the NMI line sampled at the end of the penultimate cycle of an instruction
+    /// status of the NMI line sampled during the penultimate cycle of an instruction
     cached_nmi: bool,
+    /// The NMI line still has to be sampled during this cycle
+    poll_nmi: bool,
     reset_signal: bool,
     take_interrupt: bool,
     interrupt_type: InterruptType,
+    /// A KIL opcode has halted the CPU, only a reset recovers it
+    pub(crate) jammed: bool,
+    #[serde(skip)]
+    profile: Option<Box<OpcodeProfile>>,
 
     dma_addr: u16,
     hijack_read: DmaHijack,
     copy_buffer: u8,
     dma_cycles: u16,
+    /// Cycles the CPU was halted by OAM and DMC DMAs, cleared at the start of each frame
+    pub(crate) dma_stall_cycles: u32,
 
-    ram: Vec<u8>,
+    pub(crate) ram: Vec<u8>,
 }
 
 impl Cpu {
@@ -100,16 +127,21 @@ impl Cpu {
 
             cached_irq: false,
             irq_signal: false,
+            external_irq: false,
             cached_nmi: false,
+            poll_nmi: false,
             nmi_signal: false,
             reset_signal: false,
             take_interrupt: false,
             interrupt_type: InterruptType::None,
+            jammed: false,
+            profile: None,
 
             dma_cycles: 0,
             hijack_read: DmaHijack::None,
             copy_buffer: 0,
             dma_addr: 0,
+            dma_stall_cycles: 0,
 
             ram: vec![0; 0x800],
         }
@@ -117,24 +149,54 @@ impl Cpu {
 }
 
 impl Nes {
+    pub(crate) fn cpu_enable_profiling(&mut self, enabled: bool) {
+        self.cpu.profile = enabled.then(|| Box::new(OpcodeProfile::new()));
+    }
+
+    pub(crate) fn cpu_profile(&self) -> Option<OpcodeProfile> {
+        self.cpu.profile.as_deref().cloned()
+    }
+
     pub(crate) fn cpu_gen_reset(&mut self) {
         // FIXME: cpu reset is broken...
         self.cpu.current_instruction = 0;
         self.cpu.take_interrupt = true;
         self.cpu.reset_signal = true;
         self.cpu.interrupt_type = InterruptType::Reset;
+        self.cpu.jammed = false;
         self.cpu_write(0x4015, 0);
         self.cpu.reset_signal = false;
     }
 
+    /// Read cycle of the CPU itself, the DMC DMA can halt the CPU before it. Other reads
+    /// (debuggers, tests) go through `cpu_read` directly and don't clock anything.
+    #[inline]
+    fn bus_read(&mut self, index: usize) -> u8 {
+        if self.apu_dmc_dma_pending() {
+            self.dmc_dma();
+        }
+
+        self.cpu_read(index)
+    }
+
     #[inline]
     pub(crate) fn cpu_read(&mut self, index: usize) -> u8 {
         self.cpu.open_bus = match index {
             0x4020..=0xFFFF => self.mapper.cpu_read(index).unwrap_or(self.cpu.open_bus),
             0..=0x1FFF => self.cpu.ram[index & 0x7FF],
             0x2000..=0x3FFF => self.ppu_read_reg(index),
-            0x4000..=0x4014 | 0x4017..=0x401F => self.cpu.open_bus,
-            0x4016 => (self.cpu.open_bus & 0xE0) | self.controller.read_reg(),
+            0x4000..=0x4014 | 0x4018..=0x401F => self.cpu.open_bus,
+            0x4016 => {
+                let val = (self.cpu.open_bus & 0xE0) | self.controller.read_reg();
+                self.controller.log_read(self.frame_count, 0x4016, val);
+                val
+            }
+            // TODO: second controller
+            0x4017 => {
+                self.controller
+                    .log_read(self.frame_count, 0x4017, self.cpu.open_bus);
+                self.cpu.open_bus
+            }
             0x4015 => self.apu_read_status(),
             _ => unreachable!("memory access into unmapped address: 0x{:X}", index),
         };
@@ -147,6 +209,103 @@ impl Nes {
         return self.cpu.open_bus;
     }
 
+    /// Reads RAM or the cartridge without any side effects, other addresses return the open bus
+    pub(crate) fn cpu_peek(&self, index: usize) -> u8 {
+        match index {
+            0..=0x1FFF => self.cpu.ram[index & 0x7FF],
+            0x4020..=0xFFFF => self.mapper.cpu_read(index).unwrap_or(self.cpu.open_bus),
+            _ => self.cpu.open_bus,
+        }
+    }
+
+    /// Fills `dst` like cpu_peek, internal RAM is copied in chunks up to the end of each mirror
+    pub(crate) fn cpu_peek_range(&self, start: u16, dst: &mut [u8]) {
+        let mut addr = start;
+        let mut dst = dst;
+        while !dst.is_empty() {
+            let count = if addr < 0x2000 {
+                let offset = usize::from(addr) & 0x7FF;
+                let count = dst.len().min(0x800 - offset);
+                dst[..count].copy_from_slice(&self.cpu.ram[offset..offset + count]);
+                count
+            } else {
+                dst[0] = self.cpu_peek(usize::from(addr));
+                1
+            };
+
+            dst = &mut dst[count..];
+            addr = addr.wrapping_add(count as u16);
+        }
+    }
+
+    /// Whether the next cpu_tick is going to perform an OAM DMA cycle instead of an instruction
+    #[inline]
+    pub(crate) fn cpu_in_dma(&self) -> bool {
+        self.cpu.dma_cycles != 0 || matches!(self.cpu.hijack_read, DmaHijack::Hijacked)
+    }
+
+    pub(crate) fn cpu_snapshot(&self) -> CpuSnapshot {
+        let flags = [
+            (self.cpu.n, 7),
+            (self.cpu.v, 6),
+            (true, 5),
+            (self.cpu.d, 3),
+            (self.cpu.i, 2),
+            (self.cpu.z, 1),
+            (self.cpu.c, 0),
+        ];
+
+        CpuSnapshot {
+            a: self.cpu.a,
+            x: self.cpu.x,
+            y: self.cpu.y,
+            sp: self.cpu.sp,
+            p: flags
+                .iter()
+                .fold(0, |p, &(flag, bit)| p | (u8::from(flag) << bit)),
+            pc: self.cpu_next_instruction_addr(),
+            cycle_count: self.get_cycle_count(),
+            irq_line: self.cpu.irq_signal || self.cpu.external_irq || self.apu_irq_line(),
+            nmi_line: self.cpu.nmi_signal,
+        }
+    }
+
+    pub(crate) fn cpu_set_irq(&mut self, source: IrqSource, state: bool) {
+        match source {
+            IrqSource::FrameCounter => self.apu_set_frame_irq(state),
+            IrqSource::Dmc => self.apu_set_dmc_irq(state),
+            IrqSource::Mapper => self.cpu.irq_signal = state,
+            IrqSource::External => self.cpu.external_irq = state,
+        }
+    }
+
+    /// Address of the instruction the next cpu_tick is going to execute
+    pub(crate) fn cpu_next_instruction_addr(&self) -> u16 {
+        if self.cpu.take_interrupt {
+            self.cpu.pc
+        } else {
+            self.cpu.pc.wrapping_sub(1)
+        }
+    }
+
+    /// Opcode of the instruction the next cpu_tick is going to execute, None if it's going to
+    /// run an interrupt sequence instead
+    pub(crate) fn cpu_next_opcode(&self) -> Option<u8> {
+        (!self.cpu.take_interrupt).then_some(self.cpu.current_instruction)
+    }
+
+    /// Continues execution at `pc`, must be called between instructions
+    pub(crate) fn cpu_set_pc(&mut self, pc: u16) {
+        if self.cpu.take_interrupt {
+            // The interrupt sequence doesn't increment PC
+            self.cpu.pc = pc;
+        } else {
+            self.cpu.current_instruction = self.cpu_peek(pc as usize);
+            self.cpu.pc = pc.wrapping_add(1);
+        }
+        self.cpu.ab = self.cpu.pc;
+    }
+
     #[inline]
     pub(crate) fn cpu_write(&mut self, index: usize, val: u8) {
         match index {
@@ -159,11 +318,20 @@ impl Nes {
             }
             0x4015 => self.apu_write_reg(index, val),
             0x4016 => self.controller.write_reg(val),
-            0x4017 => self.apu_write_reg(index, val),
-            0x4018..=0x401F => (),
+            // $4018 - $401F are the APU test registers, normally disabled
+            0x4017..=0x401F => self.apu_write_reg(index, val),
             0x4020..=0xFFFF => {
-                self.mapper
-                    .cpu_write(index, val, self.cycle_count, &mut self.cpu.irq_signal)
+                self.mapper.cpu_write(
+                    index,
+                    val,
+                    self.cycle_count,
+                    &mut self.cpu.irq_signal,
+                    &mut self.mapper_write_callback,
+                );
+                // The write might have switched CHR banks or the mirroring, PRG-RAM writes can't
+                if index >= 0x8000 {
+                    self.ppu.debug_cache.invalidate_chr();
+                }
             }
             _ => unreachable!("Error: memory access into unmapped address: 0x{:X}", index),
         }
@@ -179,10 +347,10 @@ impl Nes {
         }
 
         if self.cpu.dma_cycles == 1 && self.cpu.odd_cycle {
-            self.cpu_read(self.cpu.ab as usize);
+            self.bus_read(self.cpu.ab as usize);
         } else if self.cpu.dma_cycles >= 1 {
             if self.cpu.dma_cycles & 1 != 0 {
-                self.cpu_read(self.cpu.dma_addr as usize);
+                self.bus_read(self.cpu.dma_addr as usize);
                 self.cpu.dma_addr = self.cpu.dma_addr.wrapping_add(1);
                 self.cpu.copy_buffer = self.cpu.db
             } else {
@@ -198,6 +366,40 @@ impl Nes {
             }
         }
     }
+
+    /// https://wiki.nesdev.org/w/index.php?title=DMA#DMC_DMA
+    /// The DMC DMA can only halt the CPU on a read cycle, so this is called before the read
+    /// takes place. The halted read is then performed after the DMA (without emulating the
+    /// repeated reads of the halted cycles).
+    #[inline]
+    fn dmc_dma(&mut self) {
+        if self.cpu.dma_cycles != 0 {
+            // The DMC fetch takes the OAM DMA's get cycle, then the OAM DMA has to realign
+            // itself, so it only gets delayed by 2 cycles
+            self.dmc_dma_get();
+            self.clock_ppu_apu();
+        } else {
+            // Halt cycle, dummy cycle and an optional alignment cycle before the get cycle.
+            // The cycles taken from an OAM DMA are already counted by it.
+            let start = self.cycle_count;
+            self.clock_ppu_apu();
+            self.clock_ppu_apu();
+            if self.cpu.odd_cycle {
+                self.clock_ppu_apu();
+            }
+            self.dmc_dma_get();
+            self.cpu.dma_stall_cycles += (self.cycle_count - start) as u32;
+        }
+    }
+
+    #[inline]
+    fn dmc_dma_get(&mut self) {
+        let addr = self.apu_dmc_address() as usize;
+        let val = self.mapper.cpu_read(addr).unwrap_or(self.cpu.open_bus);
+        self.cpu.open_bus = val;
+        self.apu_dmc_fill(val);
+        self.clock_ppu_apu();
+    }
 }
 
 // Helper macros
@@ -211,7 +413,7 @@ macro_rules! check_read_hijack {
 
 macro_rules! cycle {
     ($self:ident) => {
-        $self.cpu_read($self.cpu.ab as usize);
+        $self.bus_read($self.cpu.ab as usize);
         check_read_hijack!($self);
     };
 }
@@ -219,7 +421,7 @@ macro_rules! cycle {
 macro_rules! penultimate_cycle {
     ($self:ident) => {
         $self.cache_interrupts();
-        $self.cpu_read($self.cpu.ab as usize);
+        $self.bus_read($self.cpu.ab as usize);
         check_read_hijack!($self);
     };
 }
@@ -227,23 +429,43 @@ macro_rules! penultimate_cycle {
 macro_rules! last_cycle {
     ($self:ident) => {
         $self.check_interrupts();
-        $self.cpu_read($self.cpu.ab as usize);
+        $self.bus_read($self.cpu.ab as usize);
         check_read_hijack!($self);
     };
 }
 
 impl Nes {
     pub(crate) fn cpu_tick(&mut self) {
+        let in_dma = self.cpu_in_dma();
+        let dma_start = self.cycle_count;
         self.dma();
         if self.cpu.dma_cycles != 0 {
             self.clock_ppu_apu();
+        }
+
+        // The last DMA cycle has fetched the next opcode, it gets executed by the next tick
+        if in_dma {
+            self.cpu.dma_stall_cycles += (self.cycle_count - dma_start) as u32;
+            return;
+        }
+
+        // The rest of the console keeps running
+        if self.cpu.jammed {
+            self.clock_ppu_apu();
             return;
         }
 
+        let power_on = self.cycle_count == 0;
+        let profile_start = self
+            .cpu
+            .profile
+            .is_some()
+            .then_some((self.cpu.current_instruction, self.cycle_count));
+
         match self.cpu.current_instruction {
             0x00 => self.brk(),
             0x01 => self.indirect_x(Nes::ora),
-            0x02 => self.immediate(Nes::halt),
+            0x02 => self.jam(),
             0x03 => self.indirect_x_illegal(Nes::slo),
             0x04 => self.zero_page(|_, _| ()),
             0x05 => self.zero_page(Nes::ora),
@@ -259,7 +481,7 @@ impl Nes {
             0x0F => self.absolute_rmw(Nes::slo),
             0x10 => self.relative(!self.cpu.n),
             0x11 => self.indirect_y(Nes::ora),
-            0x12 => self.immediate(Nes::halt),
+            0x12 => self.jam(),
             0x13 => self.indirect_y_illegal(Nes::slo),
             0x14 => self.zero_page_x(|_, _| ()),
             0x15 => self.zero_page_x(Nes::ora),
@@ -275,7 +497,7 @@ impl Nes {
             0x1F => self.absolute_x_rmw(Nes::slo),
             0x20 => self.jsr(),
             0x21 => self.indirect_x(Nes::and),
-            0x22 => self.immediate(Nes::halt),
+            0x22 => self.jam(),
             0x23 => self.indirect_x_illegal(Nes::rla),
             0x24 => self.zero_page(Nes::bit),
             0x25 => self.zero_page(Nes::and),
@@ -291,7 +513,7 @@ impl Nes {
             0x2F => self.absolute_rmw(Nes::rla),
             0x30 => self.relative(self.cpu.n),
             0x31 => self.indirect_y(Nes::and),
-            0x32 => self.immediate(Nes::halt),
+            0x32 => self.jam(),
             0x33 => self.indirect_y_illegal(Nes::rla),
             0x34 => self.zero_page_x(|_, _| ()),
             0x35 => self.zero_page_x(Nes::and),
@@ -307,7 +529,7 @@ impl Nes {
             0x3F => self.absolute_x_rmw(Nes::rla),
             0x40 => self.rti(),
             0x41 => self.indirect_x(Nes::eor),
-            0x42 => self.immediate(Nes::halt),
+            0x42 => self.jam(),
             0x43 => self.indirect_x_illegal(Nes::sre),
             0x44 => self.zero_page(|_, _| ()),
             0x45 => self.zero_page(Nes::eor),
@@ -323,7 +545,7 @@ impl Nes {
             0x4F => self.absolute_rmw(Nes::sre),
             0x50 => self.relative(!self.cpu.v),
             0x51 => self.indirect_y(Nes::eor),
-            0x52 => self.immediate(Nes::halt),
+            0x52 => self.jam(),
             0x53 => self.indirect_y_illegal(Nes::sre),
             0x54 => self.zero_page_x(|_, _| ()),
             0x55 => self.zero_page_x(Nes::eor),
@@ -339,7 +561,7 @@ impl Nes {
             0x5F => self.absolute_x_rmw(Nes::sre),
             0x60 => self.rts(),
             0x61 => self.indirect_x(Nes::adc),
-            0x62 => self.immediate(Nes::halt),
+            0x62 => self.jam(),
             0x63 => self.indirect_x_illegal(Nes::rra),
             0x64 => self.zero_page(|_, _| ()),
             0x65 => self.zero_page(Nes::adc),
@@ -355,7 +577,7 @@ impl Nes {
             0x6F => self.absolute_rmw(Nes::rra),
             0x70 => self.relative(self.cpu.v),
             0x71 => self.indirect_y(Nes::adc),
-            0x72 => self.immediate(Nes::halt),
+            0x72 => self.jam(),
             0x73 => self.indirect_y_illegal(Nes::rra),
             0x74 => self.zero_page_x(|_, _| ()),
             0x75 => self.zero_page_x(Nes::adc),
@@ -387,7 +609,7 @@ impl Nes {
             0x8F => self.absolute(Nes::aax),
             0x90 => self.relative(!self.cpu.c),
             0x91 => self.indirect_y_st(Nes::sta),
-            0x92 => self.immediate(Nes::halt),
+            0x92 => self.jam(),
             0x93 => self.indirect_y_st(Nes::ahx),
             0x94 => self.zero_page_x_st(Nes::sty),
             0x95 => self.zero_page_x_st(Nes::sta),
@@ -419,7 +641,7 @@ impl Nes {
             0xAF => self.absolute(Nes::lax),
             0xB0 => self.relative(self.cpu.c),
             0xB1 => self.indirect_y(Nes::lda),
-            0xB2 => self.immediate(Nes::halt),
+            0xB2 => self.jam(),
             0xB3 => self.indirect_y(Nes::lax),
             0xB4 => self.zero_page_x(Nes::ldy),
             0xB5 => self.zero_page_x(Nes::lda),
@@ -451,7 +673,7 @@ impl Nes {
             0xCF => self.absolute_rmw(Nes::dcp),
             0xD0 => self.relative(!self.cpu.z),
             0xD1 => self.indirect_y(Nes::cmp),
-            0xD2 => self.immediate(Nes::halt),
+            0xD2 => self.jam(),
             0xD3 => self.indirect_y_illegal(Nes::dcp),
             0xD4 => self.zero_page_x(|_, _| ()),
             0xD5 => self.zero_page_x(Nes::cmp),
@@ -483,7 +705,7 @@ impl Nes {
             0xEF => self.absolute_rmw(Nes::isc),
             0xF0 => self.relative(self.cpu.z),
             0xF1 => self.indirect_y(Nes::sbc),
-            0xF2 => self.immediate(Nes::halt),
+            0xF2 => self.jam(),
             0xF3 => self.indirect_y_illegal(Nes::isc),
             0xF4 => self.zero_page_x(|_, _| ()),
             0xF5 => self.zero_page_x(Nes::sbc),
@@ -499,8 +721,22 @@ impl Nes {
             0xFF => self.absolute_x_rmw(Nes::isc),
         };
 
-        self.load_next_instruction();
-        self.clock_ppu_apu();
+        if !self.cpu.jammed {
+            self.load_next_instruction();
+            self.clock_ppu_apu();
+        }
+
+        if let (Some((opcode, start)), Some(profile)) = (profile_start, &mut self.cpu.profile) {
+            profile.executions[usize::from(opcode)] += 1;
+            profile.cycles[usize::from(opcode)] += self.cycle_count - start;
+        }
+
+        // The first tick is the reset sequence
+        if power_on {
+            if let Some(boot_hook) = self.boot_hook.take() {
+                boot_hook(self);
+            }
+        }
     }
 }
 
@@ -1440,14 +1676,18 @@ impl Nes {
         }
         self.sp_to_ab();
         self.cpu.sp = (self.cpu.sp as u8).wrapping_sub(1);
+        // An NMI that gets detected after this cycle is too late to hijack the sequence
+        self.cache_interrupts();
 
         self.clock_ppu_apu();
 
         // Cycle 3
+        let vector = self.interrupt_address();
+        // The B flag is only pushed as set by the BRK instruction, even when it gets hijacked
         if !(self.cpu.take_interrupt && self.cpu.reset_signal) {
-            self.push_status(true);
+            self.push_status(!self.cpu.take_interrupt);
         }
-        self.cpu.ab = self.interrupt_address();
+        self.cpu.ab = vector;
         self.cpu.take_interrupt = false;
         self.cpu.interrupt_type = InterruptType::None;
 
@@ -2026,9 +2266,11 @@ impl Nes {
         self.cpu.v = self.cpu.c != ((self.cpu.a >> 5) & 1 == 1);
     }
 
-    fn halt(&mut self, _: u8) {
-        // TODO: shouldn't panic here
-        panic!("The CPU executed a halt instruction, this implies either an emulator bug, or a game bug.");
+    /// The KIL opcodes lock up the CPU, the next opcode is never fetched
+    fn jam(&mut self) {
+        cycle!(self);
+        self.cpu.jammed = true;
+        self.clock_ppu_apu();
     }
 }
 
@@ -2038,7 +2280,7 @@ impl Nes {
     fn load_next_instruction(&mut self) {
         self.cache_interrupts();
         let int = if self.cpu.take_interrupt { 0 } else { 1 };
-        self.cpu_read(self.cpu.ab as usize);
+        self.bus_read(self.cpu.ab as usize);
         check_read_hijack!(self);
         self.cpu.current_instruction = int * self.cpu.db;
         self.cpu.pc = (self.cpu.pc).wrapping_add(int as u16);
@@ -2047,8 +2289,18 @@ impl Nes {
 
     #[inline]
     fn cache_interrupts(&mut self) {
-        self.cpu.cached_irq = self.cpu.irq_signal;
-        self.cpu.cached_nmi = self.cpu.nmi_signal;
+        self.cpu.cached_irq = self.cpu.irq_signal || self.cpu.external_irq || self.apu_irq_line();
+        self.cpu.poll_nmi = true;
+    }
+
+    /// The edge detector samples the NMI line halfway through the cycle (during φ2), so an NMI
+    /// raised on the first PPU dot of the cycle is still detected
+    #[inline]
+    pub(crate) fn cpu_poll_nmi(&mut self) {
+        if self.cpu.poll_nmi {
+            self.cpu.poll_nmi = false;
+            self.cpu.cached_nmi = self.cpu.nmi_signal;
+        }
     }
 
     #[inline]
@@ -2074,8 +2326,11 @@ impl Nes {
         // For example, if NMI is asserted during the first four ticks of a BRK instruction,
         // the BRK instruction will execute normally at first (PC increments will occur and
         // the status word will be pushed with the B flag set), but execution will branch to
-        // the NMI vector instead of the IRQ/BRK vector
-        if self.cpu.nmi_signal {
+        // the NMI vector instead of the IRQ/BRK vector.
+        // The hijacking NMI is serviced by this sequence, so it mustn't be taken again afterwards.
+        if self.cpu.cached_nmi && !matches!(self.cpu.interrupt_type, InterruptType::Reset) {
+            self.cpu.nmi_signal = false;
+            self.cpu.cached_nmi = false;
             return 0xFFFA;
         }
 
@@ -1,28 +1,44 @@
+use std::{collections::VecDeque, fs, io, mem, path::Path};
+
 use thiserror::Error;
 
 mod apu;
+mod builder;
 mod cartridge;
 mod controller;
 mod cpu;
 mod mapper;
+mod pacer;
+mod patch;
 mod ppu;
 mod replay;
+mod snapshot;
 #[cfg(test)]
 mod tests;
 
 use apu::Apu;
-use cartridge::{Cartridge, ConsoleType, Region};
-use controller::Controller;
+use cartridge::{Cartridge, ConsoleType};
 use cpu::Cpu;
 use mapper::BaseMapper;
 use ppu::Ppu;
 
 use serde::{Deserialize, Serialize};
 
-pub use cartridge::BankSize;
-pub use controller::Button;
-pub use ppu::PALETTE;
+pub use apu::{ApuIrq, AudioChannel, DmcState};
+pub use builder::{NesBuilder, RamInit};
+pub use cartridge::{BankSize, Region, RomEntry};
+pub use controller::{Button, ControllerButtons, ControllerRead};
+pub use cpu::OpcodeProfile;
+pub use mapper::{MapperWrite, MapperWriteCallback};
+pub use pacer::FramePacer;
+pub use ppu::{NAMETABLE_HEIGHT, NAMETABLE_WIDTH, PALETTE, PATTERN_TABLE_SIZE};
 pub use replay::ReplayInputs;
+pub use snapshot::{ApuSnapshot, CpuSnapshot, DebugSnapshot, MapperBanks, PpuSnapshot};
+
+/// See [`Nes::set_boot_hook`]
+pub type BootHook = Box<dyn FnOnce(&mut Nes) + Send>;
+/// See [`Nes::set_unimplemented_write_hook`], called with the address and the value
+pub type WriteHook = Box<dyn FnMut(u16, u8) + Send>;
 
 #[derive(Serialize, Deserialize)]
 pub struct Nes {
@@ -33,71 +49,631 @@ pub struct Nes {
     mapper: BaseMapper,
 
     controller: controller::Controller,
+    /// See [`Nes::queue_input_script`]
+    input_script: VecDeque<(u32, ControllerButtons)>,
+
+    region: Region,
+    /// Extra CPU-only scanlines inserted after the visible frame
+    overclock_scanlines: u16,
+    /// CPU cycles left until the PPU and APU resume
+    overclock_cycles: u32,
+    /// CPU cycles run while the PPU and APU were halted since power-on
+    overclocked_total: u64,
+    /// Dots run ahead of the CPU by [`Nes::step_ppu_dot`], the next CPU cycles skip them
+    ppu_dots_ahead: u32,
+
+    /// Runs once, right after the reset sequence
+    #[serde(skip)]
+    boot_hook: Option<BootHook>,
+    #[serde(skip)]
+    breakpoints: Vec<u16>,
+    #[serde(skip)]
+    unimplemented_write_hook: Option<WriteHook>,
+    #[serde(skip)]
+    mapper_write_callback: Option<MapperWriteCallback>,
 
     frame_ready: bool,
     /// CPU cycle count
     cycle_count: u64,
+    /// Added to the reported cycle count, see [`Nes::set_cycle_origin`]
+    cycle_offset: u64,
     frame_count: u64,
+
+    /// Used again when another cartridge is loaded
+    options: NesBuilder,
 }
 
 // TODO: wrap inner NES into some Console struct
 
 impl Nes {
+    /// Cold boot with the default [`NesBuilder`] options, see [`Nes::power_cycle`]
     pub fn new(rom: &[u8]) -> Result<Nes, NesError> {
-        let cartridge = Cartridge::from_rom(rom)?;
+        NesBuilder::new().build_from_bytes(rom)
+    }
 
-        let mut nes = Nes {
-            cpu: Cpu::new(),
-            ppu: Ppu::new(),
-            apu: Apu::new(),
+    /// Cold boot with a ROM file patched by an IPS or BPS file, see [`NesBuilder::patch`]
+    pub fn new_patched(rom_path: &Path, patch_path: &Path) -> Result<Nes, NesError> {
+        NesBuilder::new()
+            .patch(patch_path)
+            .build_from_path(rom_path)
+    }
 
-            mapper: BaseMapper::new(cartridge)?,
+    /// Powers the console on with another cartridge, as if it was a new [`Nes`] built with
+    /// the same options. Runtime settings (volume, frame skip, overclocking, breakpoints and
+    /// hooks) are kept. On error the current game keeps running.
+    pub fn load_cartridge_from_bytes(&mut self, rom: &[u8]) -> Result<(), NesError> {
+        let mut nes = self.options.for_next_cartridge().build_from_bytes(rom)?;
+
+        nes.apu_copy_output_settings(&self.apu);
+        nes.ppu_set_frame_skip(self.ppu_frame_skip());
+        nes.ppu_set_sprite_limit(self.ppu_sprite_limit());
+        nes.overclock_scanlines = self.overclock_scanlines;
+        nes.boot_hook = self.boot_hook.take();
+        nes.breakpoints = mem::take(&mut self.breakpoints);
+        nes.unimplemented_write_hook = self.unimplemented_write_hook.take();
+        nes.mapper_write_callback = self.mapper_write_callback.take();
+
+        *self = nes;
+        Ok(())
+    }
 
-            controller: Controller::new(),
+    /// Lists the iNES ROMs in `dir` without loading them, see [`RomEntry`]
+    pub fn scan_directory(dir: &Path) -> Vec<RomEntry> {
+        cartridge::scan_directory(dir)
+    }
 
-            frame_ready: false,
-            cycle_count: 0,
+    pub fn set_button_state(&mut self, button: controller::Button, state: bool) {
+        self.controller.set_button(button, state);
+    }
 
-            frame_count: 0,
-        };
+    pub fn controller_buttons(&self) -> ControllerButtons {
+        self.controller.buttons()
+    }
 
-        nes.cpu_gen_reset();
-        Ok(nes)
+    /// Schedules button states by frame: the buttons of an entry are set once
+    /// [`Nes::get_frame_count`] reaches its frame, before that frame runs, and they stay until
+    /// the next entry. Entries can be queued in any order, the ones of past frames are applied
+    /// before the next frame.
+    pub fn queue_input_script(&mut self, script: Vec<(u32, ControllerButtons)>) {
+        self.queue_script_inputs(script);
     }
 
-    pub fn set_button_state(&mut self, button: controller::Button, state: bool) {
-        self.controller.set_button(button, state);
+    /// Registers a function that runs exactly once, after the power-on reset sequence and before
+    /// the first instruction of the game. Once the emulation has started, it runs after the next
+    /// [`Nes::power_cycle`] instead.
+    pub fn set_boot_hook(&mut self, hook: BootHook) {
+        self.boot_hook = Some(hook);
     }
 
+    /// Registers a function that's called on writes to registers that aren't emulated yet
+    /// (currently the APU test registers at $4018 - $401F)
+    pub fn set_unimplemented_write_hook(&mut self, hook: WriteHook) {
+        self.unimplemented_write_hook = Some(hook);
+    }
+
+    /// Registers a function that's called on every write to a mapper register, with the decoded
+    /// effect of the write. For debugging bank switching.
+    pub fn set_mapper_write_callback(&mut self, callback: MapperWriteCallback) {
+        self.mapper_write_callback = Some(callback);
+    }
+
+    /// Address of the next instruction
+    pub fn get_pc(&self) -> u16 {
+        self.cpu_next_instruction_addr()
+    }
+
+    /// Moves the program counter, the next instruction executes at `pc`
+    pub fn set_pc(&mut self, pc: u16) {
+        self.cpu_set_pc(pc);
+    }
+
+    /// Reads CPU RAM or cartridge memory without side effects
+    pub fn peek(&self, addr: u16) -> u8 {
+        self.cpu_peek(addr as usize)
+    }
+
+    /// Writes to the CPU address space, with the same side effects as a CPU write
+    pub fn poke(&mut self, addr: u16, val: u8) {
+        self.cpu_write(addr as usize, val);
+    }
+
+    /// Same as [`Nes::peek`] for `len` bytes starting at `start`, wrapping around at $FFFF.
+    /// Panics if `dst` is shorter than `len`.
+    pub fn read_range(&self, start: u16, len: usize, dst: &mut [u8]) {
+        self.cpu_peek_range(start, &mut dst[..len]);
+    }
+
+    /// Same as [`Nes::poke`] for every byte of `src`, wrapping around at $FFFF
+    pub fn write_range(&mut self, start: u16, src: &[u8]) {
+        let mut addr = start;
+        for &val in src {
+            self.cpu_write(addr as usize, val);
+            addr = addr.wrapping_add(1);
+        }
+    }
+
+    /// Raises an NMI as if the PPU had signaled one, it's taken after the next instruction
+    pub fn trigger_nmi(&mut self) {
+        self.cpu.nmi_signal = true;
+    }
+
+    /// Pulls the IRQ line low on behalf of `source`, the line stays asserted until
+    /// [`Nes::clear_irq`] is called or the device acknowledges the IRQ by itself
+    pub fn assert_irq(&mut self, source: IrqSource) {
+        self.cpu_set_irq(source, true);
+    }
+
+    pub fn clear_irq(&mut self, source: IrqSource) {
+        self.cpu_set_irq(source, false);
+    }
+
+    /// Starts logging the last `capacity` reads of $4016 and $4017, 0 disables the log.
+    /// See [`Nes::controller_read_log`]
+    pub fn set_controller_read_log(&mut self, capacity: usize) {
+        self.controller.set_read_log_capacity(capacity);
+    }
+
+    /// Controller port reads, oldest first
+    pub fn controller_read_log(&self) -> &VecDeque<ControllerRead> {
+        self.controller.read_log()
+    }
+
+    /// Presses the reset button (warm reset). The RAM, the cartridge memory and the mapper
+    /// registers are kept, so games can tell a reset from a power-on by checking a signature in
+    /// RAM. The PPU clears PPUCTRL, PPUMASK, the scroll registers and the odd frame flag, and
+    /// ignores writes to $2000, $2001, $2005 and $2006 until the end of the next vblank.
     pub fn reset(&mut self) {
+        self.ppu_reset();
+        self.cpu_gen_reset();
+    }
+
+    /// Turns the console off and on again (cold boot), like [`Nes::new`] does. The RAM is filled
+    /// again according to [`NesBuilder::ram_init`], and the CPU, the PPU, the APU and the mapper
+    /// start from their power-on state. The cartridge memory and the runtime settings are kept.
+    pub fn power_cycle(&mut self) {
+        let (cpu, ppu, apu) = self.options.power_on_chips(self.region);
+        let frame_skip = self.ppu_frame_skip();
+        let sprite_limit = self.ppu_sprite_limit();
+
+        self.cpu = cpu;
+        self.ppu = ppu;
+        let apu = mem::replace(&mut self.apu, apu);
+        self.apu_copy_output_settings(&apu);
+        self.ppu_set_frame_skip(frame_skip);
+        self.ppu_set_sprite_limit(sprite_limit);
+        self.mapper.reset();
+        self.controller = controller::Controller::new();
+
+        self.overclock_cycles = 0;
+        self.overclocked_total = 0;
+        self.ppu_dots_ahead = 0;
+        self.frame_ready = false;
+        self.cycle_count = 0;
+        self.cycle_offset = 0;
+        self.frame_count = 0;
+
         self.cpu_gen_reset();
     }
 
-    pub fn run_one_frame(&mut self) {
+    /// Stops early if the CPU jams, see [`Nes::is_cpu_jammed`]
+    pub fn run_one_frame(&mut self) -> StopReason {
+        self.apply_script_inputs();
+        self.cpu.dma_stall_cycles = 0;
         while !self.frame_ready {
+            if self.cpu.jammed {
+                return StopReason::CpuJammed;
+            }
             self.cpu_tick();
         }
         self.frame_ready = false;
 
         self.frame_count += 1;
+        StopReason::Completed
+    }
+
+    /// Whether a KIL opcode has halted the CPU, only [`Nes::reset`] or [`Nes::power_cycle`]
+    /// recover it. The PPU and the APU keep running.
+    pub fn is_cpu_jammed(&self) -> bool {
+        self.cpu.jammed
+    }
+
+    /// CPU cycles taken by OAM and DMC DMAs during the last (or current) frame run by
+    /// [`Nes::run_one_frame`]
+    pub fn dma_stall_cycles_this_frame(&self) -> u32 {
+        self.cpu.dma_stall_cycles
+    }
+
+    /// Starts counting the executions and cycles of each opcode, see [`Nes::opcode_profile`].
+    /// Calling it again clears the counts.
+    pub fn enable_opcode_profiling(&mut self) {
+        self.cpu_enable_profiling(true);
+    }
+
+    pub fn disable_opcode_profiling(&mut self) {
+        self.cpu_enable_profiling(false);
+    }
+
+    /// The counts since profiling was enabled, or `None` if it isn't
+    pub fn opcode_profile(&self) -> Option<OpcodeProfile> {
+        self.cpu_profile()
+    }
+
+    /// Runs whole frames until `condition` (checked after every frame) returns true, or until
+    /// `max_frames` frames have been run or the CPU jams. Returns whether the condition was met.
+    pub fn run_until<F: FnMut(&Nes) -> bool>(&mut self, max_frames: u64, mut condition: F) -> bool {
+        for _ in 0..max_frames {
+            if self.run_one_frame() == StopReason::CpuJammed {
+                return false;
+            }
+            if condition(self) {
+                return true;
+            }
+        }
+
+        false
+    }
+
+    /// Draws only every (n + 1)th frame into the frame buffer, for slow hosts. Skipped frames are
+    /// still fully emulated (including audio), the frame buffer keeps the last drawn frame.
+    pub fn set_frame_skip(&mut self, n: u32) {
+        self.ppu_set_frame_skip(n);
+    }
+
+    /// Gives the game `n` more scanlines worth of CPU time each frame to reduce slowdown.
+    /// The PPU and the APU are halted during the extra cycles, so the games that rely on exact
+    /// timing might break. 0 (the default) disables overclocking.
+    pub fn set_overclock_scanlines(&mut self, n: u16) {
+        self.overclock_scanlines = n;
+    }
+
+    /// Disabling the limit of 8 sprites per scanline removes sprite flicker, only the rendering
+    /// is affected (the sprite overflow flag still works). Enabled by default.
+    pub fn set_sprite_limit_enabled(&mut self, enabled: bool) {
+        self.ppu_set_sprite_limit(enabled);
     }
 
     pub fn run_cpu_cycle(&mut self) {
         self.cpu_tick();
     }
 
+    /// Advances the PPU by one dot while the CPU, the APU and the mapper are held. The PPU runs
+    /// ahead of the CPU until the next CPU cycles have skipped as many dots, so the normal ratio
+    /// holds again after the stepped dots have been caught up with.
+    pub fn step_ppu_dot(&mut self) {
+        self.ppu_tick();
+        self.ppu_dots_ahead += 1;
+    }
+
+    /// The scanline and the dot (0 - 340) that the PPU is about to process
+    pub fn ppu_position(&self) -> (u16, u16) {
+        self.ppu_scanline_and_dot()
+    }
+
+    /// Executes `n` instructions, or less if a breakpoint is reached first.
+    /// Interrupt sequences (including the power-on reset) count as an instruction,
+    /// OAM DMA doesn't.
+    pub fn run_instructions(&mut self, n: u64) -> StopReason {
+        for _ in 0..n {
+            if let Some(pc) = self.run_instruction() {
+                return StopReason::Breakpoint(pc);
+            }
+            if self.cpu.jammed {
+                return StopReason::CpuJammed;
+            }
+        }
+
+        StopReason::Completed
+    }
+
+    /// Runs a JSR together with the whole subroutine, until execution continues after the JSR.
+    /// Any other instruction is executed on its own. Stops early at breakpoints, and gives up
+    /// after a second of emulated time.
+    pub fn step_over(&mut self) -> StopReason {
+        if self.cpu_next_opcode() != Some(0x20) {
+            return self.run_instructions(1);
+        }
+
+        let return_addr = self.cpu_next_instruction_addr().wrapping_add(3);
+        let sp = self.cpu.sp;
+        let timeout = self.step_timeout();
+        loop {
+            let breakpoint = self.run_instruction();
+            if self.cpu_next_instruction_addr() == return_addr && self.cpu.sp == sp {
+                return StopReason::Completed;
+            }
+
+            if let Some(pc) = breakpoint {
+                return StopReason::Breakpoint(pc);
+            }
+            if self.cpu.jammed {
+                return StopReason::CpuJammed;
+            }
+            if self.cycle_count >= timeout {
+                return StopReason::TimedOut;
+            }
+        }
+    }
+
+    /// Runs until the current subroutine or interrupt handler returns with an RTS or an RTI.
+    /// Returns from nested subroutines are recognized by the stack pointer. Stops early at
+    /// breakpoints, and gives up after a second of emulated time.
+    pub fn step_out(&mut self) -> StopReason {
+        let sp = self.cpu.sp;
+        let timeout = self.step_timeout();
+        loop {
+            let opcode = self.cpu_next_opcode();
+            let breakpoint = self.run_instruction();
+            // The stack pointer is above its starting value after returning to the caller
+            let returned = self.cpu.sp.wrapping_sub(sp) as i8 > 0;
+            if matches!(opcode, Some(0x40 | 0x60)) && returned {
+                return StopReason::Completed;
+            }
+
+            if let Some(pc) = breakpoint {
+                return StopReason::Breakpoint(pc);
+            }
+            if self.cpu.jammed {
+                return StopReason::CpuJammed;
+            }
+            if self.cycle_count >= timeout {
+                return StopReason::TimedOut;
+            }
+        }
+    }
+
+    /// Cycle count at which [`Nes::step_over`] and [`Nes::step_out`] give up, a second from now,
+    /// since the subroutine might never return
+    fn step_timeout(&self) -> u64 {
+        self.cycle_count + self.region.cpu_clock_rate() as u64
+    }
+
+    /// Execution stops before the instruction at `addr`
+    pub fn add_breakpoint(&mut self, addr: u16) {
+        if !self.breakpoints.contains(&addr) {
+            self.breakpoints.push(addr);
+        }
+    }
+
+    pub fn remove_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.retain(|&bp| bp != addr);
+    }
+
+    pub fn get_breakpoints(&self) -> &[u16] {
+        &self.breakpoints
+    }
+
+    /// Outputs interleaved left / right samples, the channels can be panned with
+    /// [`Nes::set_channel_pan`]. Disabled by default, the NES only has a mono output.
+    pub fn set_stereo(&mut self, stereo: bool) {
+        self.apu_set_stereo(stereo);
+    }
+
+    /// From -1.0 (left) to 1.0 (right), only used in stereo mode. Channels are centered by default.
+    pub fn set_channel_pan(&mut self, channel: AudioChannel, pan: f32) {
+        self.apu_set_pan(channel, pan);
+    }
+
+    /// Audio samples produced since the last [`Nes::clear_audio_buffer`] call, interleaved
+    /// left / right samples in stereo mode
+    pub fn get_audio_buffer(&self) -> &[f32] {
+        self.apu_audio_buffer()
+    }
+
+    pub fn clear_audio_buffer(&mut self) {
+        self.apu_clear_audio_buffer();
+    }
+
+    /// A coarse fingerprint of `samples` for audio regression tests: the RMS of 16 equally long
+    /// windows, quantized to 16 levels of 1/64. Compare fingerprints with
+    /// [`Nes::audio_fingerprints_match`] rather than `==`.
+    pub fn audio_fingerprint(samples: &[f32]) -> u64 {
+        apu::audio_fingerprint(samples)
+    }
+
+    /// Whether the level of every window differs by at most 1 between the two fingerprints,
+    /// which absorbs floating point differences near the edge of a level
+    pub fn audio_fingerprints_match(a: u64, b: u64) -> bool {
+        apu::audio_fingerprints_match(a, b)
+    }
+
+    /// Runs whole frames until the audio buffer holds at least `n` samples (a stereo sample
+    /// counts once), returns the number of frames run. For frontends driven by the audio
+    /// callback. `n` is capped to the capacity of the buffer. Stops early if the CPU jams.
+    pub fn run_until_audio_samples(&mut self, n: usize) -> u64 {
+        let n = n.min(self.apu_audio_capacity());
+
+        let mut frames = 0;
+        while self.apu_buffered_samples() < n {
+            if self.run_one_frame() == StopReason::CpuJammed {
+                break;
+            }
+            frames += 1;
+        }
+        frames
+    }
+
+    /// Scales the audio output, samples are clamped to [-1.0, 1.0] after applying the gain.
+    /// The default gain is 1.0, negative values are treated as 0.
+    pub fn set_master_volume(&mut self, gain: f32) {
+        self.apu_set_master_volume(gain);
+    }
+
+    pub fn get_master_volume(&self) -> f32 {
+        self.apu_master_volume()
+    }
+
+    /// Reports the pending frame and DMC interrupts. Unlike reading $4015, this doesn't
+    /// acknowledge the frame interrupt.
+    pub fn apu_irq_pending(&self) -> ApuIrq {
+        self.apu_irq_flags()
+    }
+
+    /// The progress of the DMC sample playback, for debugging
+    pub fn dmc_state(&self) -> DmcState {
+        self.apu_dmc_state()
+    }
+
+    /// The last value written to each APU register from $4000 to $4017, indexed by
+    /// `addr - 0x4000`. $4014 (OAM DMA) and $4016 (controller strobe) aren't APU registers and
+    /// always read as 0.
+    pub fn apu_register_shadow(&self) -> [u8; 0x18] {
+        self.apu_register_values()
+    }
+
+    /// Starts recording every APU channel into its own track, see [`Nes::stop_multitrack_recording`]
+    pub fn start_multitrack_recording(&mut self) {
+        self.apu_start_multitrack();
+    }
+
+    /// Returns the tracks recorded since the last [`Nes::start_multitrack_recording`] call,
+    /// in the order: pulse 1, pulse 2, triangle, noise, DMC.
+    /// The tracks are empty if no recording was running.
+    pub fn stop_multitrack_recording(&mut self) -> [Vec<f32>; 5] {
+        self.apu_stop_multitrack()
+    }
+
     pub fn get_frame_buffer(&self) -> &[u8] {
         &self.ppu.output_buffer
     }
 
+    /// Whether the last frame run by [`Nes::run_one_frame`] wrote pixels to the frame buffer.
+    /// False on frames skipped by [`Nes::set_frame_skip`] or without [`NesBuilder::rendering`],
+    /// so the frontend can skip uploading the frame. The pixels aren't compared with the
+    /// previous frame. Reading the flag clears it, so it stays false while the frontend is
+    /// paused.
+    pub fn framebuffer_changed_this_frame(&mut self) -> bool {
+        self.ppu_take_frame_buffer_changed()
+    }
+
+    /// For drawing overlays onto the last frame (palette indices like [`Nes::get_frame_buffer`]).
+    /// The emulator never reads the pixels back, the changes are overwritten when the next frame
+    /// is rendered.
+    pub fn framebuffer_mut(&mut self) -> &mut [u8] {
+        &mut self.ppu.output_buffer
+    }
+
+    /// The last frame (palette indices like [`Nes::get_frame_buffer`]) upscaled by `scale`
+    /// (1 - 8) with nearest-neighbor sampling. `dst` must hold exactly
+    /// `256 * scale * 240 * scale` pixels.
+    pub fn framebuffer_scaled(&self, scale: u8, dst: &mut [u8]) -> Result<(), NesError> {
+        let scaled_len = 256 * 240 * usize::from(scale).pow(2);
+        if !(1..=8).contains(&scale) || dst.len() != scaled_len {
+            return Err(NesError::InvalidFrameScale {
+                scale,
+                len: dst.len(),
+            });
+        }
+
+        self.ppu_scale_frame(usize::from(scale), dst);
+        Ok(())
+    }
+
+    /// Saves the last frame as a binary PPM image
+    pub fn save_screenshot(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, self.ppu_frame_ppm())
+    }
+
+    /// RGB image of the pattern table at $0000 (0) or $1000 (1), drawn with the first background
+    /// palette. The image is cached until CHR memory, CHR banks or palettes change.
+    pub fn ppu_pattern_table(&mut self, table: usize) -> &[u8] {
+        self.ppu_debug_pattern_table(table & 1)
+    }
+
+    /// RGB image of one of the 4 nametables, starting at $2000. The image is cached until
+    /// CHR memory, nametables, palettes or the background pattern table change.
+    pub fn ppu_nametable(&mut self, index: usize) -> &[u8] {
+        self.ppu_debug_nametable(index & 3)
+    }
+
+    /// How many sprites of OAM are drawn on the visible scanline `line` with the current sprite
+    /// size, ignoring the limit of 8 sprites per scanline, see [`Nes::set_sprite_limit_enabled`]
+    pub fn sprites_on_scanline(&self, line: u16) -> u8 {
+        self.ppu_sprites_on_scanline(line)
+    }
+
+    /// The 4 background and 4 sprite palettes as RGB colors of [`PALETTE`], with the greyscale
+    /// and color emphasis bits of $2001 applied
+    pub fn current_palettes(&self) -> [[[u8; 3]; 4]; 8] {
+        self.ppu_debug_palettes()
+    }
+
+    /// Draws the transparent pixels of [`Nes::ppu_nametable`] in the given RGB color instead of
+    /// the backdrop color, to tell them apart from opaque pixels. The frame buffer isn't affected.
+    pub fn set_debug_backdrop(&mut self, color: Option<[u8; 3]>) {
+        self.ppu_set_debug_backdrop(color);
+    }
+
+    /// Plain copy of the main CPU, PPU, APU and mapper registers for assertions in tests
+    pub fn debug_snapshot(&self) -> DebugSnapshot {
+        self.snapshot()
+    }
+
     pub fn save_state(&self) -> Result<Vec<u8>, NesError> {
         bincode::serialize(self).map_err(|_| NesError::InvalidSaveState)
     }
 
-    pub fn load_state(save: &[u8]) -> Result<Nes, NesError> {
-        let nes: Nes = bincode::deserialize(save).map_err(|_| NesError::InvalidSaveState)?;
+    /// Replaces the current state with `save`, unless it has been saved with a different game
+    /// (checked by the mapper chip and a hash of the ROM). Breakpoints and hooks are kept.
+    pub fn load_state(&mut self, save: &[u8]) -> Result<(), NesError> {
+        let nes = Nes::deserialize_state(save)?;
+        self.check_state(&nes)?;
 
-        Ok(nes)
+        self.replace_state(nes);
+        Ok(())
+    }
+
+    /// Same as [`Nes::save_state`] in a human-readable format, for diffing states when debugging.
+    /// JSON states are a lot larger than the binary ones.
+    #[cfg(feature = "json-state")]
+    pub fn save_state_json(&self) -> Result<String, NesError> {
+        serde_json::to_string_pretty(self).map_err(|_| NesError::InvalidSaveState)
+    }
+
+    /// Same as [`Nes::load_state`] for states saved by [`Nes::save_state_json`]
+    #[cfg(feature = "json-state")]
+    pub fn load_state_json(&mut self, save: &str) -> Result<(), NesError> {
+        let nes: Nes = serde_json::from_str(save).map_err(|_| NesError::InvalidSaveState)?;
+        self.check_state(&nes)?;
+
+        self.replace_state(nes);
+        Ok(())
+    }
+
+    /// Same as [`Nes::load_state`] without checking that `save` belongs to the loaded game. The
+    /// state brings its own ROM along, but the behavior of a game running from another game's
+    /// state (and its save data) is undefined.
+    pub fn load_state_force(&mut self, save: &[u8]) -> Result<(), NesError> {
+        let nes = Nes::deserialize_state(save)?;
+        self.replace_state(nes);
+        Ok(())
+    }
+
+    fn deserialize_state(save: &[u8]) -> Result<Nes, NesError> {
+        bincode::deserialize(save).map_err(|_| NesError::InvalidSaveState)
+    }
+
+    /// Rejects the deserialized state `nes` if it doesn't belong to the loaded game
+    fn check_state(&self, nes: &Nes) -> Result<(), NesError> {
+        let (saved, current) = (nes.mapper.chip_name(), self.mapper.chip_name());
+        if saved != current {
+            return Err(StateError::MapperMismatch { saved, current }.into());
+        }
+
+        let (found, expected) = (nes.mapper.cartridge_hash(), self.mapper.cartridge_hash());
+        if found != expected {
+            return Err(StateError::CartridgeMismatch { expected, found }.into());
+        }
+
+        Ok(())
+    }
+
+    /// Replaces the emulator state with `nes`, the breakpoints and hooks are kept
+    fn replace_state(&mut self, mut nes: Nes) {
+        nes.boot_hook = self.boot_hook.take();
+        nes.breakpoints = mem::take(&mut self.breakpoints);
+        nes.unimplemented_write_hook = self.unimplemented_write_hook.take();
+        nes.mapper_write_callback = self.mapper_write_callback.take();
+        *self = nes;
     }
 
     pub fn drive_replay_inputs(&mut self, inputs: &ReplayInputs) {
@@ -108,35 +684,153 @@ impl Nes {
         &self.mapper.cartridge
     }
 
+    /// The whole PRG ROM of the cartridge
+    pub fn dump_prg(&self) -> &[u8] {
+        self.mapper.cartridge.prg_rom()
+    }
+
+    /// The whole CHR ROM of the cartridge, or the current contents of CHR RAM
+    pub fn dump_chr(&self) -> &[u8] {
+        self.mapper.cartridge.chr()
+    }
+
+    /// Writes [`Nes::dump_prg`] and [`Nes::dump_chr`] to raw binary files
+    pub fn dump_to_files(&self, prg_path: &Path, chr_path: &Path) -> io::Result<()> {
+        fs::write(prg_path, self.dump_prg())?;
+        fs::write(chr_path, self.dump_chr())
+    }
+
+    /// Whether the PPU has finished its power-on warm-up and accepts writes to all of its registers
+    pub fn ppu_warmed_up(&self) -> bool {
+        self.ppu_writes_enabled()
+    }
+
+    pub fn region(&self) -> Region {
+        self.region
+    }
+
     pub fn get_frame_count(&self) -> u64 {
         self.frame_count
     }
 
     pub fn get_cycle_count(&self) -> u64 {
-        self.cycle_count
+        self.cycle_count.wrapping_add(self.cycle_offset)
+    }
+
+    /// Makes the CPU cycle count read `cycles` from now on, to line it up with a reference log.
+    /// Only [`Nes::get_cycle_count`] follows the origin, [`Nes::master_cycle_count`] and
+    /// [`Nes::ppu_dot_count`] still count from power-on, like the emulation itself.
+    pub fn set_cycle_origin(&mut self, cycles: u64) {
+        self.cycle_offset = cycles.wrapping_sub(self.cycle_count);
+    }
+
+    /// Moves the PPU to `dot` (0 - 340) of `scanline` (0 up to the pre-render scanline), to line
+    /// it up with a reference log. The rest of the PPU state is left alone, so
+    /// [`Nes::ppu_dot_count`] keeps counting from power-on.
+    pub fn set_ppu_origin(&mut self, scanline: u16, dot: u16) -> Result<(), NesError> {
+        self.ppu_set_scanline_and_dot(scanline, dot)
+    }
+
+    /// Whether the PPU is rendering an odd frame. On NTSC, the pre-render scanline of odd frames
+    /// is one dot shorter when rendering is enabled.
+    pub fn is_odd_frame(&self) -> bool {
+        self.ppu_odd_frame()
+    }
+
+    /// PPU dots since power-on, excluding the PPU halted during overclocking
+    pub fn ppu_dot_count(&self) -> u64 {
+        self.ppu_total_dots()
+    }
+
+    /// Master clock cycles since power-on, this is the CPU cycle count (without the offset of
+    /// [`Nes::set_cycle_origin`]) multiplied by [`Region::master_clock_divider`] (12 on NTSC)
+    pub fn master_cycle_count(&self) -> u64 {
+        self.cycle_count * self.region.master_clock_divider()
     }
 }
 
+/// https://wiki.nesdev.org/w/index.php?title=PPU_power_up_state
+/// The PPU ignores writes to $2000, $2001, $2005 and $2006 for this many CPU cycles after power-on
+const PPU_WARM_UP_CYCLES: u64 = 29658;
+
 impl Nes {
+    /// Runs one instruction, returns its address if the next one is at a breakpoint
+    fn run_instruction(&mut self) -> Option<u16> {
+        self.cpu_tick();
+        while self.cpu_in_dma() {
+            self.cpu_tick();
+        }
+
+        let pc = self.cpu_next_instruction_addr();
+        self.breakpoints.contains(&pc).then_some(pc)
+    }
+
     fn clock_ppu_apu(&mut self) {
-        self.cpu.odd_cycle = !self.cpu.odd_cycle;
+        // The CPU bus keeps running during overclocking, so the cycle count (which also times
+        // the MMC1 consecutive write check) includes the extra cycles
         self.cycle_count += 1;
-        if self.cycle_count == 29658 {
-            self.ppu_enable_writes();
+
+        if self.overclock_cycles > 0 {
+            // The APU and the mapper stay halted too, so the audio and the IRQs stay in sync
+            // with the video
+            self.overclock_cycles -= 1;
+            self.overclocked_total += 1;
+            return;
         }
 
-        for _ in 0..3 {
-            self.ppu_tick();
+        // The warm-up is measured in console time, like the rest of the PPU
+        if self.cycle_count - self.overclocked_total == PPU_WARM_UP_CYCLES {
+            self.ppu_enable_writes();
         }
 
+        self.cpu.odd_cycle = !self.cpu.odd_cycle;
+        self.clock_ppu();
+        self.cpu_poll_nmi();
+        self.clock_ppu();
+        self.clock_ppu();
+
         self.apu_tick();
+        self.mapper.cpu_clock(&mut self.cpu.irq_signal);
+    }
+
+    #[inline]
+    fn clock_ppu(&mut self) {
+        if self.ppu_dots_ahead > 0 {
+            self.ppu_dots_ahead -= 1;
+        } else {
+            self.ppu_tick();
+        }
     }
 }
 
+/// Why [`Nes::run_instructions`] has returned
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StopReason {
+    /// All of the requested instructions were executed
+    Completed,
+    /// The next instruction is at a breakpoint
+    Breakpoint(u16),
+    /// The CPU has executed a KIL opcode, see [`Nes::is_cpu_jammed`]
+    CpuJammed,
+    /// [`Nes::step_over`] or [`Nes::step_out`] ran for a second without the subroutine returning
+    TimedOut,
+}
+
+/// The devices that can pull the CPU IRQ line low, see [`Nes::assert_irq`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IrqSource {
+    /// The APU frame counter IRQ flag
+    FrameCounter,
+    /// The DMC IRQ flag
+    Dmc,
+    /// The cartridge IRQ line
+    Mapper,
+    /// A line only driven by [`Nes::assert_irq`], for testing
+    External,
+}
+
 #[derive(Error, Debug)]
 pub enum NesError {
-    #[error("iNES 2.0 binary format is not supported")]
-    Ines2Unsupported,
     #[error("iNES trainers are not supported")]
     TrainerUnsupported,
     #[error("mapper {0} is not supported")]
@@ -145,8 +839,12 @@ pub enum NesError {
     ConsoleUnsupported(ConsoleType),
     #[error("the {0} region is not supported")]
     RegionUnsupported(Region),
+    #[error("couldn't read the ROM file: {0}")]
+    Io(#[from] std::io::Error),
     #[error("the provided file is not a valid iNES ROM")]
     InvalidInesFormat,
+    #[error("the PRG / CHR ROM sizes are not valid for mapper {0}")]
+    InvalidRomSize(u32),
     #[error("games with both CHR RAM and ROM are not supported")]
     ChrRomAndRamUnsupported,
     #[error("corrupted ROM file")]
@@ -155,4 +853,29 @@ pub enum NesError {
     InvalidSaveState,
     #[error("the NES 2.0 XML Game Database contains invalid data")]
     GameDbFormat,
+    #[error("can't scale the frame by {scale} into a buffer of {len} pixels")]
+    InvalidFrameScale { scale: u8, len: usize },
+    #[error("the PPU has no dot {dot} on scanline {scanline}")]
+    InvalidPpuPosition { scanline: u16, dot: u16 },
+    #[error("the patch is not a valid IPS or BPS file")]
+    InvalidPatch,
+    #[error("the patch was made for another ROM")]
+    PatchMismatch,
+    #[error(transparent)]
+    State(#[from] StateError),
+}
+
+/// The savestate is valid, but it doesn't belong to the loaded game
+#[derive(Error, Debug)]
+pub enum StateError {
+    #[error("the savestate uses the {saved} mapper, the loaded game uses {current}")]
+    MapperMismatch {
+        saved: &'static str,
+        current: &'static str,
+    },
+    /// Hashes of the PRG and CHR ROM of the loaded game (`expected`) and the savestate (`found`)
+    #[error(
+        "the savestate belongs to another game (ROM hash {found:016X}, expected {expected:016X})"
+    )]
+    CartridgeMismatch { expected: u64, found: u64 },
 }
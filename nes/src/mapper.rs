@@ -1,9 +1,15 @@
 use serde::{Deserialize, Serialize};
 
-use super::{cartridge::Cartridge, ppu::Mirroring, NesError};
+use super::{
+    cartridge::{BankSize, Cartridge},
+    ppu::Mirroring,
+    snapshot::MapperBanks,
+    NesError,
+};
 
 mod _0_nrom;
 mod _1_mmc1;
+mod _21_vrc;
 mod _2_uxrom;
 mod _3_cnrom;
 mod _4_mmc3;
@@ -11,11 +17,46 @@ mod _7_axrom;
 
 use _0_nrom::_0Nrom;
 use _1_mmc1::_1Mmc1;
+use _21_vrc::_21Vrc;
 use _2_uxrom::_2Uxrom;
 use _3_cnrom::_3Cnrom;
 use _4_mmc3::_4Mmc3;
 use _7_axrom::_7Axrom;
 
+/// A write to a mapper register with its decoded meaning, see
+/// [`Nes::set_mapper_write_callback`](crate::Nes::set_mapper_write_callback)
+#[derive(Debug, Clone, PartialEq)]
+pub struct MapperWrite {
+    pub addr: u16,
+    pub val: u8,
+    /// e.g. "MMC3 bank select"
+    pub register: &'static str,
+    /// e.g. "target=CHR bank 2"
+    pub effect: String,
+}
+
+/// See [`Nes::set_mapper_write_callback`](crate::Nes::set_mapper_write_callback)
+pub type MapperWriteCallback = Box<dyn FnMut(&MapperWrite) + Send>;
+
+/// Reports a register write to the callback, `effect` is only formatted if a callback is set
+#[inline]
+pub fn trace_write<F: FnOnce() -> String>(
+    callback: &mut Option<MapperWriteCallback>,
+    addr: usize,
+    val: u8,
+    register: &'static str,
+    effect: F,
+) {
+    if let Some(callback) = callback {
+        callback(&MapperWrite {
+            addr: addr as u16,
+            val,
+            register,
+            effect: effect(),
+        });
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct BaseMapper {
     nt_ram: Vec<u8>,
@@ -26,23 +67,37 @@ pub struct BaseMapper {
 
 impl BaseMapper {
     pub fn new(cartridge: Cartridge) -> Result<Self, NesError> {
-        let chip = match cartridge.header.mapper {
-            0 => MapperChip::_0Nrom(_0Nrom::new(&cartridge)),
-            1 => MapperChip::_1Mmc1(_1Mmc1::new(&cartridge)),
-            2 => MapperChip::_2Uxrom(_2Uxrom::new(&cartridge)),
-            3 => MapperChip::_3Cnrom(_3Cnrom::new(&cartridge)),
-            4 => MapperChip::_4Mmc3(_4Mmc3::new(&cartridge)),
-            7 => MapperChip::_7Axrom(_7Axrom::new(&cartridge)),
-            mapper_id => return Err(NesError::UnSupportedMapper(mapper_id)),
-        };
-
         Ok(BaseMapper {
             nt_ram: vec![0; 0x1000],
+            chip: MapperChip::new(&cartridge)?,
             cartridge,
-            chip,
         })
     }
 
+    /// Name of the emulated mapper chip, savestates keep it as the variant of the chip state
+    #[inline]
+    pub fn chip_name(&self) -> &'static str {
+        match &self.chip {
+            MapperChip::_0Nrom(_) => "NROM",
+            MapperChip::_1Mmc1(_) => "MMC1",
+            MapperChip::_2Uxrom(_) => "UxROM",
+            MapperChip::_3Cnrom(_) => "CNROM",
+            MapperChip::_4Mmc3(_) => "MMC3",
+            MapperChip::_7Axrom(_) => "AxROM",
+            MapperChip::_21Vrc(_) => "VRC",
+        }
+    }
+
+    pub fn cartridge_hash(&self) -> u64 {
+        self.cartridge.hash()
+    }
+
+    /// Restores the power-on bank configuration, cartridge memory is kept
+    pub fn reset(&mut self) {
+        self.chip = MapperChip::new(&self.cartridge)
+            .expect("the mapper has already been constructed from this cartridge");
+    }
+
     /// Return None if addr isn't mapped to anything on the cartridge, Some(_) otherwise
     #[inline]
     pub fn cpu_read(&self, addr: usize) -> Option<u8> {
@@ -53,18 +108,28 @@ impl BaseMapper {
             MapperChip::_3Cnrom(cnrom) => cnrom.cpu_read(&self.cartridge, addr),
             MapperChip::_4Mmc3(mmc3) => mmc3.cpu_read(&self.cartridge, addr),
             MapperChip::_7Axrom(axrom) => axrom.cpu_read(&self.cartridge, addr),
+            MapperChip::_21Vrc(vrc) => vrc.cpu_read(&self.cartridge, addr),
         }
     }
 
     #[inline]
-    pub fn cpu_write(&mut self, addr: usize, val: u8, cpu_cycle: u64, cpu_irq: &mut bool) {
+    pub fn cpu_write(
+        &mut self,
+        addr: usize,
+        val: u8,
+        cpu_cycle: u64,
+        cpu_irq: &mut bool,
+        trace: &mut Option<MapperWriteCallback>,
+    ) {
+        let cartridge = &mut self.cartridge;
         match &mut self.chip {
-            MapperChip::_0Nrom(nrom) => nrom.cpu_write(&mut self.cartridge, addr, val),
-            MapperChip::_1Mmc1(mmc1) => mmc1.cpu_write(&mut self.cartridge, addr, val, cpu_cycle),
-            MapperChip::_2Uxrom(uxrom) => uxrom.cpu_write(addr, val),
-            MapperChip::_3Cnrom(cnrom) => cnrom.cpu_write(addr, val),
-            MapperChip::_4Mmc3(mmc3) => mmc3.cpu_write(&mut self.cartridge, addr, val, cpu_irq),
-            MapperChip::_7Axrom(axrom) => axrom.cpu_write(addr, val),
+            MapperChip::_0Nrom(nrom) => nrom.cpu_write(cartridge, addr, val),
+            MapperChip::_1Mmc1(mmc1) => mmc1.cpu_write(cartridge, addr, val, cpu_cycle, trace),
+            MapperChip::_2Uxrom(uxrom) => uxrom.cpu_write(addr, val, trace),
+            MapperChip::_3Cnrom(cnrom) => cnrom.cpu_write(addr, val, trace),
+            MapperChip::_4Mmc3(mmc3) => mmc3.cpu_write(cartridge, addr, val, cpu_irq, trace),
+            MapperChip::_7Axrom(axrom) => axrom.cpu_write(addr, val, trace),
+            MapperChip::_21Vrc(vrc) => vrc.cpu_write(cartridge, addr, val, cpu_irq, trace),
         }
     }
 
@@ -77,6 +142,7 @@ impl BaseMapper {
             MapperChip::_3Cnrom(cnrom) => cnrom.read_chr(&self.cartridge, addr),
             MapperChip::_4Mmc3(mmc3) => mmc3.read_chr(&self.cartridge, addr),
             MapperChip::_7Axrom(axrom) => axrom.read_chr(&self.cartridge, addr),
+            MapperChip::_21Vrc(vrc) => vrc.read_chr(&self.cartridge, addr),
         }
     }
 
@@ -89,6 +155,7 @@ impl BaseMapper {
             MapperChip::_3Cnrom(cnrom) => cnrom.write_chr(&mut self.cartridge, addr, val),
             MapperChip::_4Mmc3(mmc3) => mmc3.write_chr(&mut self.cartridge, addr, val),
             MapperChip::_7Axrom(axrom) => axrom.write_chr(&mut self.cartridge, addr, val),
+            MapperChip::_21Vrc(vrc) => vrc.write_chr(&mut self.cartridge, addr, val),
         }
     }
 
@@ -111,6 +178,7 @@ impl BaseMapper {
             MapperChip::_1Mmc1(mmc1) => mmc1.mirroring(),
             MapperChip::_4Mmc3(mmc3) => mmc3.mirroring(),
             MapperChip::_7Axrom(axrom) => axrom.mirroring(),
+            MapperChip::_21Vrc(vrc) => vrc.mirroring(),
         }
     }
 
@@ -123,10 +191,31 @@ impl BaseMapper {
             | MapperChip::_1Mmc1(_)
             | MapperChip::_2Uxrom(_)
             | MapperChip::_3Cnrom(_)
-            | MapperChip::_7Axrom(_) => (),
+            | MapperChip::_7Axrom(_)
+            | MapperChip::_21Vrc(_) => (),
             MapperChip::_4Mmc3(mmc3) => mmc3.notify_a12(a12, ppu_cycle, cpu_irq),
         }
     }
+
+    pub fn banks(&self) -> MapperBanks {
+        match &self.chip {
+            MapperChip::_0Nrom(nrom) => nrom.banks(),
+            MapperChip::_1Mmc1(mmc1) => mmc1.banks(),
+            MapperChip::_2Uxrom(uxrom) => uxrom.banks(),
+            MapperChip::_3Cnrom(cnrom) => cnrom.banks(),
+            MapperChip::_4Mmc3(mmc3) => mmc3.banks(),
+            MapperChip::_7Axrom(axrom) => axrom.banks(),
+            MapperChip::_21Vrc(vrc) => vrc.banks(),
+        }
+    }
+
+    /// Called on every CPU cycle, for mappers with CPU cycle based IRQ counters
+    #[inline]
+    pub fn cpu_clock(&mut self, cpu_irq: &mut bool) {
+        if let MapperChip::_21Vrc(vrc) = &mut self.chip {
+            vrc.cpu_clock(cpu_irq);
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize)]
@@ -137,4 +226,61 @@ pub enum MapperChip {
     _3Cnrom(_3Cnrom),
     _4Mmc3(_4Mmc3),
     _7Axrom(_7Axrom),
+    _21Vrc(_21Vrc),
+}
+
+impl MapperChip {
+    fn new(cartridge: &Cartridge) -> Result<Self, NesError> {
+        Self::validate_rom_size(cartridge)?;
+
+        let chip = match cartridge.header.mapper {
+            0 => MapperChip::_0Nrom(_0Nrom::new(cartridge)),
+            1 => MapperChip::_1Mmc1(_1Mmc1::new(cartridge)),
+            2 => MapperChip::_2Uxrom(_2Uxrom::new(cartridge)),
+            3 => MapperChip::_3Cnrom(_3Cnrom::new(cartridge)),
+            4 => MapperChip::_4Mmc3(_4Mmc3::new(cartridge)),
+            7 => MapperChip::_7Axrom(_7Axrom::new(cartridge)),
+            21 | 22 | 23 | 25 => MapperChip::_21Vrc(_21Vrc::new(cartridge)),
+            mapper_id => return Err(NesError::UnSupportedMapper(mapper_id)),
+        };
+
+        Ok(chip)
+    }
+
+    /// Rejects PRG / CHR sizes that aren't a whole number of banks, or that the mapper can't
+    /// address. Bank selects are wrapped at runtime, so smaller ROMs are fine.
+    fn validate_rom_size(cartridge: &Cartridge) -> Result<(), NesError> {
+        let header = &cartridge.header;
+
+        // PRG bank size, the maximum number of PRG banks, CHR bank size
+        let (prg_unit, max_prg_banks, chr_unit) = match header.mapper {
+            0 | 3 => (BankSize::Kb16, 2, BankSize::Kb8),
+            1 => (BankSize::Kb16, 32, BankSize::Kb4),
+            2 => (BankSize::Kb16, 256, BankSize::Kb8),
+            4 => (BankSize::Kb8, 64, BankSize::Kb1),
+            7 => (BankSize::Kb32, 8, BankSize::Kb8),
+            21 | 22 | 23 | 25 => (BankSize::Kb8, 32, BankSize::Kb1),
+            _ => return Ok(()),
+        };
+        let (prg_unit, chr_unit) = (prg_unit as u32, chr_unit as u32);
+
+        // MMC3 and VRC always map the last 2 banks
+        let min_prg_banks = if prg_unit == BankSize::Kb8 as u32 {
+            2
+        } else {
+            1
+        };
+        let prg_banks = header.prg_rom_size / prg_unit;
+        let chr_size = header.chr_rom_size.or(header.chr_ram_size).unwrap_or(0);
+
+        if header.prg_rom_size % prg_unit != 0
+            || !(min_prg_banks..=max_prg_banks).contains(&prg_banks)
+            || chr_size == 0
+            || chr_size % chr_unit != 0
+        {
+            return Err(NesError::InvalidRomSize(header.mapper));
+        }
+
+        Ok(())
+    }
 }
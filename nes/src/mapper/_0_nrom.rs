@@ -1,6 +1,9 @@
 use serde::{Deserialize, Serialize};
 
-use crate::cartridge::{BankSize, Cartridge};
+use crate::{
+    cartridge::{BankSize, Cartridge},
+    snapshot::MapperBanks,
+};
 
 #[derive(Serialize, Deserialize)]
 pub struct _0Nrom {
@@ -40,4 +43,11 @@ impl _0Nrom {
     pub fn read_chr(&self, cartridge: &Cartridge, addr: usize) -> u8 {
         cartridge.read_chr(addr)
     }
+
+    pub fn banks(&self) -> MapperBanks {
+        MapperBanks {
+            prg: vec![0, self.prg_1],
+            chr: vec![0],
+        }
+    }
 }
@@ -1,6 +1,8 @@
 use crate::{
     cartridge::{BankSize, Cartridge},
+    mapper::{trace_write, MapperWriteCallback},
     ppu::Mirroring,
+    snapshot::MapperBanks,
 };
 
 use serde::{Deserialize, Serialize};
@@ -19,6 +21,8 @@ pub struct _1Mmc1 {
     prg_0: usize,
     prg_1: usize,
 
+    /// 4KB units
+    chr_banks: u16,
     chr_0: usize,
     chr_1: usize,
 }
@@ -36,6 +40,10 @@ impl _1Mmc1 {
         };
 
         let prg_banks = cartridge.prg_rom_count(BankSize::Kb16) as u8;
+        let chr_banks = cartridge
+            .chr_rom_count(BankSize::Kb4)
+            .or_else(|| cartridge.chr_ram_count(BankSize::Kb4))
+            .unwrap_or(2) as u16;
 
         Self {
             shift: 0x10,
@@ -50,6 +58,7 @@ impl _1Mmc1 {
             prg_0: 0,
             prg_1: Cartridge::map_bank(prg_banks - 1, BankSize::Kb16),
 
+            chr_banks,
             chr_0: 0,
             chr_1: 0,
         }
@@ -64,7 +73,14 @@ impl _1Mmc1 {
         }
     }
 
-    pub fn cpu_write(&mut self, cartridge: &mut Cartridge, addr: usize, val: u8, cpu_cycle: u64) {
+    pub fn cpu_write(
+        &mut self,
+        cartridge: &mut Cartridge,
+        addr: usize,
+        val: u8,
+        cpu_cycle: u64,
+        trace: &mut Option<MapperWriteCallback>,
+    ) {
         match addr {
             0x6000..=0x7FFF if self.enable_ram => cartridge.write_prg_ram(addr - 0x6000, val),
             0x8000..=0xFFFF => {
@@ -84,6 +100,9 @@ impl _1Mmc1 {
                 if val & 0x80 != 0 {
                     self.shift = 0x10;
                     self.prg_mode = 3;
+                    trace_write(trace, addr, val, "MMC1 shift register", || {
+                        "reset, PRG mode=3".to_string()
+                    });
                 } else {
                     /* To change a register's value, the CPU writes five times with bit 7 clear and
                     a bit of the desired value in bit 0. On the first four writes, the MMC1 shifts
@@ -93,13 +112,26 @@ impl _1Mmc1 {
                     let shift = ((val & 1) << 4) | self.shift >> 1;
 
                     if self.shift & 1 != 0 {
-                        match addr & 0x6000 {
-                            0 => self.write_control(shift),
-                            0x2000 => self.select_chr_0(shift),
-                            0x4000 => self.select_chr_1(shift),
-                            0x6000 => self.select_prg(shift),
+                        let register = match addr & 0x6000 {
+                            0 => {
+                                self.write_control(shift);
+                                "MMC1 control"
+                            }
+                            0x2000 => {
+                                self.select_chr_0(shift);
+                                "MMC1 CHR bank 0"
+                            }
+                            0x4000 => {
+                                self.select_chr_1(shift);
+                                "MMC1 CHR bank 1"
+                            }
+                            0x6000 => {
+                                self.select_prg(shift);
+                                "MMC1 PRG bank"
+                            }
                             _ => unreachable!(),
-                        }
+                        };
+                        trace_write(trace, addr, val, register, || self.describe(addr, shift));
 
                         /* After the fifth write, the shift register is cleared automatically,
                         so a write to the shift register with bit 7 on to reset it is not needed. */
@@ -113,6 +145,31 @@ impl _1Mmc1 {
         }
     }
 
+    /// The effect of a write of `val` to the internal register at `addr`
+    fn describe(&self, addr: usize, val: u8) -> String {
+        match addr & 0x6000 {
+            0 => format!(
+                "mirroring={:?}, PRG mode={}, CHR mode={}",
+                self.mirroring,
+                self.prg_mode,
+                if self.chr_mode == 0 { "8KB" } else { "4KB" }
+            ),
+            0x2000 if self.chr_mode == 0 => format!("8KB CHR bank {}", val >> 1),
+            0x2000 => format!("4KB CHR bank {} at $0000", val & self.chr_mask),
+            0x4000 if self.chr_mode == 0 => "ignored in 8KB CHR mode".to_string(),
+            0x4000 => format!("4KB CHR bank {} at $1000", val & self.chr_mask),
+            _ => format!(
+                "PRG bank {}, PRG RAM {}",
+                val & 0xF,
+                if self.enable_ram {
+                    "enabled"
+                } else {
+                    "disabled"
+                }
+            ),
+        }
+    }
+
     /* Control (internal, $8000-$9FFF)
     4bit0
     -----
@@ -157,11 +214,11 @@ impl _1Mmc1 {
         self.enable_ram = val & 0x10 == 0;
         match self.prg_mode {
             0 | 1 => {
-                self.prg_0 = Cartridge::map_bank(val & 0xE, BankSize::Kb16);
-                self.prg_1 = Cartridge::map_bank((val & 0xE) + 1, BankSize::Kb16);
+                self.prg_0 = self.prg_bank(val & 0xE);
+                self.prg_1 = self.prg_bank((val & 0xE) + 1);
             }
-            2 => self.prg_1 = Cartridge::map_bank(val & 0xF, BankSize::Kb16),
-            3 => self.prg_0 = Cartridge::map_bank(val & 0xF, BankSize::Kb16),
+            2 => self.prg_1 = self.prg_bank(val & 0xF),
+            3 => self.prg_0 = self.prg_bank(val & 0xF),
             _ => unreachable!(),
         }
     }
@@ -177,10 +234,10 @@ impl _1Mmc1 {
     #[inline]
     fn select_chr_0(&mut self, val: u8) {
         if self.chr_mode == 1 {
-            self.chr_0 = Cartridge::map_bank(val & self.chr_mask, BankSize::Kb4);
+            self.chr_0 = self.chr_bank(val & self.chr_mask);
         } else {
-            self.chr_0 = Cartridge::map_bank(val & 0xFE, BankSize::Kb4);
-            self.chr_1 = Cartridge::map_bank((val & 0xFE) + 1, BankSize::Kb4);
+            self.chr_0 = self.chr_bank(val & 0xFE);
+            self.chr_1 = self.chr_bank((val & 0xFE) + 1);
         }
     }
 
@@ -193,10 +250,22 @@ impl _1Mmc1 {
     #[inline]
     fn select_chr_1(&mut self, val: u8) {
         if self.chr_mode == 1 {
-            self.chr_1 = Cartridge::map_bank(val & self.chr_mask, BankSize::Kb4);
+            self.chr_1 = self.chr_bank(val & self.chr_mask);
         }
     }
 
+    /// Offset of the 16KB PRG bank, selects past the end wrap around
+    #[inline]
+    fn prg_bank(&self, bank: u8) -> usize {
+        Cartridge::map_bank(bank % self.prg_banks, BankSize::Kb16)
+    }
+
+    /// Offset of the 4KB CHR bank, selects past the end wrap around
+    #[inline]
+    fn chr_bank(&self, bank: u8) -> usize {
+        Cartridge::map_bank((u16::from(bank) % self.chr_banks) as u8, BankSize::Kb4)
+    }
+
     pub fn read_chr(&self, cartridge: &Cartridge, addr: usize) -> u8 {
         match addr {
             0..=0xFFF => cartridge.read_chr(self.chr_0 + addr),
@@ -218,4 +287,11 @@ impl _1Mmc1 {
     pub fn mirroring(&self) -> Mirroring {
         self.mirroring
     }
+
+    pub fn banks(&self) -> MapperBanks {
+        MapperBanks {
+            prg: vec![self.prg_0, self.prg_1],
+            chr: vec![self.chr_0, self.chr_1],
+        }
+    }
 }
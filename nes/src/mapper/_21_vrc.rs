@@ -0,0 +1,303 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cartridge::{BankSize, Cartridge},
+    mapper::{trace_write, MapperWriteCallback},
+    ppu::Mirroring,
+    snapshot::MapperBanks,
+};
+
+/// Konami VRC2 and VRC4, mappers 21, 22, 23 and 25.
+/// https://wiki.nesdev.org/w/index.php?title=VRC2_and_VRC4
+#[derive(Serialize, Deserialize)]
+pub struct _21Vrc {
+    /// VRC2 has no IRQ, no PRG swap mode and only 2 mirroring modes
+    vrc2: bool,
+    /// VRC2a ignores the lowest bit of the CHR banks
+    chr_shift: bool,
+    /// The CPU address lines that are connected to the register select pins, some boards connect
+    /// both lines of 2 different wirings
+    reg_lines: [usize; 2],
+
+    prg_swap: bool,
+    mirroring: Mirroring,
+
+    /// 8KB units
+    prg_rom_count: u8,
+    prg_0: u8,
+    prg_1: u8,
+
+    /// 1KB units
+    chr_count: u16,
+    chr: [u16; 8],
+
+    irq_latch: u8,
+    irq_counter: u8,
+    irq_prescaler: i16,
+    irq_enabled: bool,
+    irq_enabled_after_ack: bool,
+    /// Clock the counter every CPU cycle instead of every scanline
+    irq_cycle_mode: bool,
+}
+
+impl _21Vrc {
+    pub fn new(cartridge: &Cartridge) -> Self {
+        let header = &cartridge.header;
+        // Submapper 0 = the wiring is unknown, so connect both possible ones
+        let (reg_lines, vrc2) = match (header.mapper, header.submapper) {
+            (21, 1) => ([0x02, 0x04], false),
+            (21, 2) => ([0x40, 0x80], false),
+            (21, _) => ([0x42, 0x84], false),
+            (22, _) => ([0x02, 0x01], true),
+            (23, 1) => ([0x01, 0x02], false),
+            (23, 2) => ([0x04, 0x08], false),
+            (23, 3) => ([0x01, 0x02], true),
+            (23, _) => ([0x05, 0x0A], false),
+            (25, 1) => ([0x02, 0x01], false),
+            (25, 2) => ([0x08, 0x04], false),
+            (25, 3) => ([0x02, 0x01], true),
+            (25, _) => ([0x0A, 0x05], false),
+            (mapper, _) => unreachable!("mapper {} isn't a VRC2 / VRC4", mapper),
+        };
+
+        let chr_count = cartridge
+            .chr_rom_count(BankSize::Kb1)
+            .or_else(|| cartridge.chr_ram_count(BankSize::Kb1))
+            .unwrap_or(8) as u16;
+
+        Self {
+            vrc2,
+            chr_shift: header.mapper == 22,
+            reg_lines,
+
+            prg_swap: false,
+            mirroring: header.mirroring,
+
+            prg_rom_count: cartridge.prg_rom_count(BankSize::Kb8) as u8,
+            prg_0: 0,
+            prg_1: 0,
+
+            chr_count,
+            chr: [0; 8],
+
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_prescaler: 341,
+            irq_enabled: false,
+            irq_enabled_after_ack: false,
+            irq_cycle_mode: false,
+        }
+    }
+
+    pub fn cpu_read(&self, cartridge: &Cartridge, addr: usize) -> Option<u8> {
+        let second_last = self.prg_rom_count.saturating_sub(2);
+
+        let bank = match addr {
+            0x6000..=0x7FFF => return cartridge.read_prg_ram(addr - 0x6000),
+            0x8000..=0x9FFF if self.prg_swap => second_last,
+            0x8000..=0x9FFF => self.prg_0,
+            0xA000..=0xBFFF => self.prg_1,
+            0xC000..=0xDFFF if self.prg_swap => self.prg_0,
+            0xC000..=0xDFFF => second_last,
+            0xE000..=0xFFFF => self.prg_rom_count.saturating_sub(1),
+            _ => return None,
+        };
+
+        Some(cartridge.read_prg_rom(Cartridge::map_bank(bank, BankSize::Kb8) + (addr & 0x1FFF)))
+    }
+
+    pub fn cpu_write(
+        &mut self,
+        cartridge: &mut Cartridge,
+        addr: usize,
+        val: u8,
+        cpu_irq: &mut bool,
+        trace: &mut Option<MapperWriteCallback>,
+    ) {
+        if let 0x6000..=0x7FFF = addr {
+            cartridge.write_prg_ram(addr - 0x6000, val);
+            return;
+        }
+
+        let reg = self.register(addr);
+        match (addr & 0xF000, reg) {
+            (0x8000, _) => self.prg_0 = (val & 0x1F) % self.prg_rom_count,
+            (0x9000, _) if self.vrc2 => {
+                self.mirroring = if val & 1 == 0 {
+                    Mirroring::Vertical
+                } else {
+                    Mirroring::Horizontal
+                }
+            }
+            (0x9000, 0 | 1) => {
+                self.mirroring = match val & 3 {
+                    0 => Mirroring::Vertical,
+                    1 => Mirroring::Horizontal,
+                    2 => Mirroring::SingleScreenLow,
+                    3 => Mirroring::SingleScreenHigh,
+                    _ => unreachable!(),
+                }
+            }
+            // TODO: bit 0 is the PRG RAM enable
+            (0x9000, _) => self.prg_swap = val & 2 != 0,
+            (0xA000, _) => self.prg_1 = (val & 0x1F) % self.prg_rom_count,
+            (0xB000..=0xE000, reg) => {
+                let bank = &mut self.chr[Self::chr_index(addr, reg)];
+                if reg & 1 == 0 {
+                    *bank = (*bank & !0xF) | u16::from(val & 0xF);
+                } else {
+                    *bank = (*bank & 0xF) | (u16::from(val & 0x1F) << 4);
+                }
+            }
+            (0xF000, _) if self.vrc2 => (),
+            (0xF000, 0) => self.irq_latch = (self.irq_latch & 0xF0) | (val & 0xF),
+            (0xF000, 1) => self.irq_latch = (self.irq_latch & 0xF) | (val << 4),
+            (0xF000, 2) => {
+                self.irq_enabled_after_ack = val & 1 != 0;
+                self.irq_enabled = val & 2 != 0;
+                self.irq_cycle_mode = val & 4 != 0;
+
+                if self.irq_enabled {
+                    self.irq_counter = self.irq_latch;
+                    self.irq_prescaler = 341;
+                }
+                *cpu_irq = false;
+            }
+            (0xF000, 3) => {
+                self.irq_enabled = self.irq_enabled_after_ack;
+                *cpu_irq = false;
+            }
+            _ => (),
+        }
+
+        let register = match (addr & 0xF000, reg) {
+            (0x8000, _) => "VRC PRG bank 0",
+            (0x9000, _) if self.vrc2 => "VRC mirroring",
+            (0x9000, 0 | 1) => "VRC mirroring",
+            (0x9000, _) => "VRC PRG swap mode",
+            (0xA000, _) => "VRC PRG bank 1",
+            (0xB000..=0xE000, _) => "VRC CHR bank",
+            (0xF000, _) if self.vrc2 => return,
+            (0xF000, 0 | 1) => "VRC IRQ latch",
+            (0xF000, 2) => "VRC IRQ control",
+            (0xF000, _) => "VRC IRQ acknowledge",
+            _ => return,
+        };
+        trace_write(trace, addr, val, register, || self.describe(addr, reg));
+    }
+
+    /// The effect of a write to the register `reg` at `addr`
+    fn describe(&self, addr: usize, reg: usize) -> String {
+        let (prg_0_addr, fixed_addr) = if self.prg_swap {
+            ("$C000", "$8000")
+        } else {
+            ("$8000", "$C000")
+        };
+
+        match (addr & 0xF000, reg) {
+            (0x8000, _) => format!("PRG bank {} at {}", self.prg_0, prg_0_addr),
+            (0x9000, _) if self.vrc2 => format!("mirroring={:?}", self.mirroring),
+            (0x9000, 0 | 1) => format!("mirroring={:?}", self.mirroring),
+            (0x9000, _) => format!("second to last PRG bank at {}", fixed_addr),
+            (0xA000, _) => format!("PRG bank {} at $A000", self.prg_1),
+            (0xB000..=0xE000, _) => {
+                let index = Self::chr_index(addr, reg);
+                format!("CHR bank {}={}", index, self.chr[index])
+            }
+            (0xF000, 0 | 1) => format!("latch={}", self.irq_latch),
+            (0xF000, 2) => format!(
+                "enabled={}, enabled after ack={}, mode={}",
+                self.irq_enabled,
+                self.irq_enabled_after_ack,
+                if self.irq_cycle_mode {
+                    "cycle"
+                } else {
+                    "scanline"
+                }
+            ),
+            _ => format!("enabled={}", self.irq_enabled),
+        }
+    }
+
+    /// 2 banks per register range, low 4 bits and high 5 bits of each bank
+    fn chr_index(addr: usize, reg: usize) -> usize {
+        (((addr & 0xF000) - 0xB000) >> 11) | (reg >> 1)
+    }
+
+    /// Translates the CPU address to the selected register (0 - 3) according to the board wiring
+    fn register(&self, addr: usize) -> usize {
+        let low = (addr & self.reg_lines[0] != 0) as usize;
+        let high = (addr & self.reg_lines[1] != 0) as usize;
+        (high << 1) | low
+    }
+
+    fn chr_addr(&self, addr: usize) -> usize {
+        let mut bank = self.chr[addr >> 10];
+        if self.chr_shift {
+            bank >>= 1;
+        }
+
+        usize::from(bank % self.chr_count) * BankSize::Kb1 as usize + (addr & 0x3FF)
+    }
+
+    pub fn read_chr(&self, cartridge: &Cartridge, addr: usize) -> u8 {
+        cartridge.read_chr(self.chr_addr(addr))
+    }
+
+    pub fn write_chr(&mut self, cartridge: &mut Cartridge, addr: usize, val: u8) {
+        if cartridge.has_chr_ram() {
+            cartridge.write_chr(self.chr_addr(addr), val);
+        }
+    }
+
+    pub fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    /// https://wiki.nesdev.org/w/index.php?title=VRC_IRQ
+    #[inline]
+    pub fn cpu_clock(&mut self, cpu_irq: &mut bool) {
+        if !self.irq_enabled {
+            return;
+        }
+
+        if self.irq_cycle_mode {
+            self.clock_irq_counter(cpu_irq);
+        } else {
+            // The prescaler divides the CPU clock by 113.667 to approximate scanlines
+            self.irq_prescaler -= 3;
+            if self.irq_prescaler <= 0 {
+                self.irq_prescaler += 341;
+                self.clock_irq_counter(cpu_irq);
+            }
+        }
+    }
+
+    #[inline]
+    fn clock_irq_counter(&mut self, cpu_irq: &mut bool) {
+        if self.irq_counter == 0xFF {
+            self.irq_counter = self.irq_latch;
+            *cpu_irq = true;
+        } else {
+            self.irq_counter += 1;
+        }
+    }
+
+    pub fn banks(&self) -> MapperBanks {
+        let prg = (0x8000..=0xE000)
+            .step_by(0x2000)
+            .map(|addr| match (addr, self.prg_swap) {
+                (0x8000, true) | (0xC000, false) => self.prg_rom_count.saturating_sub(2),
+                (0x8000, false) | (0xC000, true) => self.prg_0,
+                (0xA000, _) => self.prg_1,
+                _ => self.prg_rom_count.saturating_sub(1),
+            })
+            .map(|bank| Cartridge::map_bank(bank, BankSize::Kb8))
+            .collect();
+
+        MapperBanks {
+            prg,
+            chr: (0..8).map(|bank| self.chr_addr(bank << 10)).collect(),
+        }
+    }
+}
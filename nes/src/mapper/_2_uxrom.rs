@@ -1,20 +1,26 @@
 use serde::{Deserialize, Serialize};
 
-use crate::cartridge::{BankSize, Cartridge};
+use crate::{
+    cartridge::{BankSize, Cartridge},
+    mapper::{trace_write, MapperWriteCallback},
+    snapshot::MapperBanks,
+};
 
 #[derive(Serialize, Deserialize)]
 pub struct _2Uxrom {
+    prg_count: usize,
     prg_0: usize,
     prg_1: usize,
 }
 
 impl _2Uxrom {
     pub fn new(cartridge: &Cartridge) -> Self {
-        let banks = cartridge.prg_rom_count(BankSize::Kb16) as u8;
+        let prg_count = cartridge.prg_rom_count(BankSize::Kb16) as usize;
 
         Self {
+            prg_count,
             prg_0: 0,
-            prg_1: Cartridge::map_bank(banks - 1, BankSize::Kb16),
+            prg_1: (prg_count - 1) * BankSize::Kb16 as usize,
         }
     }
 
@@ -26,9 +32,14 @@ impl _2Uxrom {
         }
     }
 
-    pub fn cpu_write(&mut self, addr: usize, val: u8) {
+    pub fn cpu_write(&mut self, addr: usize, val: u8, trace: &mut Option<MapperWriteCallback>) {
         if let 0x8000..=0xFFFF = addr {
-            self.prg_0 = Cartridge::map_bank(val, BankSize::Kb16);
+            // Selects past the end wrap around, like on boards that leave the upper lines unconnected
+            let bank = usize::from(val) % self.prg_count;
+            self.prg_0 = bank * BankSize::Kb16 as usize;
+            trace_write(trace, addr, val, "UxROM bank select", || {
+                format!("PRG bank {} at $8000", bank)
+            });
         }
     }
 
@@ -41,4 +52,11 @@ impl _2Uxrom {
             cartridge.write_chr(addr, val);
         }
     }
+
+    pub fn banks(&self) -> MapperBanks {
+        MapperBanks {
+            prg: vec![self.prg_0, self.prg_1],
+            chr: vec![0],
+        }
+    }
 }
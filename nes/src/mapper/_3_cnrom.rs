@@ -1,11 +1,16 @@
 use serde::{Deserialize, Serialize};
 
-use crate::cartridge::{BankSize, Cartridge};
+use crate::{
+    cartridge::{BankSize, Cartridge},
+    mapper::{trace_write, MapperWriteCallback},
+    snapshot::MapperBanks,
+};
 
 #[derive(Serialize, Deserialize)]
 pub struct _3Cnrom {
     prg_1: usize,
 
+    chr_count: u8,
     chr_0: usize,
 }
 
@@ -19,7 +24,18 @@ impl _3Cnrom {
             _ => Cartridge::map_bank(1, BankSize::Kb16),
         };
 
-        Self { prg_1, chr_0: 0 }
+        // Only 2 bits are decoded
+        let chr_count = cartridge
+            .chr_rom_count(BankSize::Kb8)
+            .or_else(|| cartridge.chr_ram_count(BankSize::Kb8))
+            .unwrap_or(1)
+            .min(4) as u8;
+
+        Self {
+            prg_1,
+            chr_count,
+            chr_0: 0,
+        }
     }
 
     pub fn cpu_read(&self, cartridge: &Cartridge, addr: usize) -> Option<u8> {
@@ -30,9 +46,13 @@ impl _3Cnrom {
         }
     }
 
-    pub fn cpu_write(&mut self, addr: usize, val: u8) {
+    pub fn cpu_write(&mut self, addr: usize, val: u8, trace: &mut Option<MapperWriteCallback>) {
         if let 0x8000..=0xFFFF = addr {
-            self.chr_0 = Cartridge::map_bank(val & 3, BankSize::Kb8);
+            let bank = (val & 3) % self.chr_count;
+            self.chr_0 = Cartridge::map_bank(bank, BankSize::Kb8);
+            trace_write(trace, addr, val, "CNROM bank select", || {
+                format!("CHR bank {}", bank)
+            });
         }
     }
 
@@ -45,4 +65,11 @@ impl _3Cnrom {
             cartridge.write_chr(self.chr_0 + addr, val)
         }
     }
+
+    pub fn banks(&self) -> MapperBanks {
+        MapperBanks {
+            prg: vec![0, self.prg_1],
+            chr: vec![self.chr_0],
+        }
+    }
 }
@@ -2,7 +2,9 @@ use serde::{Deserialize, Serialize};
 
 use crate::{
     cartridge::{BankSize, Cartridge},
+    mapper::{trace_write, MapperWriteCallback},
     ppu::Mirroring,
+    snapshot::MapperBanks,
 };
 
 #[derive(Serialize, Deserialize)]
@@ -106,6 +108,7 @@ impl _4Mmc3 {
         addr: usize,
         val: u8,
         cpu_irq: &mut bool,
+        trace: &mut Option<MapperWriteCallback>,
     ) {
         match addr {
             0x6000..=0x7FFF => cartridge.write_prg_ram(addr - 0x6000, val),
@@ -182,6 +185,42 @@ impl _4Mmc3 {
             }
             _ => unreachable!(),
         }
+
+        if addr >= 0x8000 {
+            let register = match addr & 0xE001 {
+                0x8000 => "MMC3 bank select",
+                0x8001 => "MMC3 bank data",
+                0xA000 => "MMC3 mirroring",
+                0xA001 => "MMC3 PRG RAM protect",
+                0xC000 => "MMC3 IRQ latch",
+                0xC001 => "MMC3 IRQ reload",
+                0xE000 => "MMC3 IRQ disable",
+                _ => "MMC3 IRQ enable",
+            };
+            trace_write(trace, addr, val, register, || self.describe(addr, val));
+        }
+    }
+
+    /// The effect of a write of `val` to the register at `addr`
+    fn describe(&self, addr: usize, val: u8) -> String {
+        let target = match self.bank_update {
+            bank @ 0..=5 => format!("CHR bank {}", bank),
+            bank => format!("PRG bank {}", bank - 6),
+        };
+
+        match addr & 0xE001 {
+            0x8000 => format!(
+                "target={}, PRG mode={}, CHR mode={}",
+                target, self.prg_bank_mode, self.chr_bank_mode
+            ),
+            0x8001 => format!("{}={}", target, val),
+            0xA000 => format!("mirroring={:?}", self.mirroring),
+            0xA001 => "ignored".to_string(),
+            0xC000 => format!("latch={}", val),
+            0xC001 => "counter reloaded on the next scanline".to_string(),
+            0xE000 => "IRQs disabled and acknowledged".to_string(),
+            _ => "IRQs enabled".to_string(),
+        }
     }
 
     pub fn read_chr(&self, cartridge: &Cartridge, addr: usize) -> u8 {
@@ -279,4 +318,23 @@ impl _4Mmc3 {
             _ => (),
         }
     }
+
+    pub fn banks(&self) -> MapperBanks {
+        let prg = match self.prg_bank_mode {
+            0 => vec![self.prg_0, self.prg_1, self.prg_end_2, self.prg_end_1],
+            _ => vec![self.prg_end_2, self.prg_1, self.prg_0, self.prg_end_1],
+        };
+
+        // 2 2KB banks and 4 1KB banks
+        let chr = match self.chr_bank_mode {
+            0 => vec![
+                self.chr_0, self.chr_1, self.chr_2, self.chr_3, self.chr_4, self.chr_5,
+            ],
+            _ => vec![
+                self.chr_2, self.chr_3, self.chr_4, self.chr_5, self.chr_0, self.chr_1,
+            ],
+        };
+
+        MapperBanks { prg, chr }
+    }
 }
@@ -2,21 +2,25 @@ use serde::{Deserialize, Serialize};
 
 use crate::{
     cartridge::{BankSize, Cartridge},
+    mapper::{trace_write, MapperWriteCallback},
     ppu::Mirroring,
+    snapshot::MapperBanks,
 };
 
 #[derive(Serialize, Deserialize)]
 pub struct _7Axrom {
     mirroring: Mirroring,
 
+    prg_count: u8,
     prg_0: usize,
 }
 
 impl _7Axrom {
-    pub fn new(_cartridge: &Cartridge) -> Self {
+    pub fn new(cartridge: &Cartridge) -> Self {
         Self {
             mirroring: Mirroring::SingleScreenLow,
 
+            prg_count: cartridge.prg_rom_count(BankSize::Kb32) as u8,
             prg_0: 0,
         }
     }
@@ -28,15 +32,20 @@ impl _7Axrom {
         }
     }
 
-    pub fn cpu_write(&mut self, addr: usize, val: u8) {
+    pub fn cpu_write(&mut self, addr: usize, val: u8, trace: &mut Option<MapperWriteCallback>) {
         if let 0x8000..=0xFFFF = addr {
-            self.prg_0 = Cartridge::map_bank(val & 7, BankSize::Kb32);
+            let bank = (val & 7) % self.prg_count;
+            self.prg_0 = Cartridge::map_bank(bank, BankSize::Kb32);
 
             self.mirroring = if val & 0x10 != 0 {
                 Mirroring::SingleScreenHigh
             } else {
                 Mirroring::SingleScreenLow
-            }
+            };
+
+            trace_write(trace, addr, val, "AxROM bank select", || {
+                format!("PRG bank {}, mirroring={:?}", bank, self.mirroring)
+            });
         }
     }
 
@@ -53,4 +62,11 @@ impl _7Axrom {
     pub fn mirroring(&self) -> Mirroring {
         self.mirroring
     }
+
+    pub fn banks(&self) -> MapperBanks {
+        MapperBanks {
+            prg: vec![self.prg_0],
+            chr: vec![0],
+        }
+    }
 }
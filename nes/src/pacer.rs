@@ -0,0 +1,70 @@
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::cartridge::Region;
+
+/// Keeps the emulation running at the real console speed.
+/// Call [`FramePacer::wait_for_next_frame`] after every [`crate::Nes::run_one_frame`].
+pub struct FramePacer {
+    frame_duration: Duration,
+    /// Deadline of the frame that is being emulated
+    next_frame: Instant,
+    fast_forward: bool,
+    /// The most frames that are allowed to be skipped at once, the pacer resynchronizes
+    /// when it falls further behind
+    max_frame_skip: u32,
+}
+
+impl FramePacer {
+    pub fn new(region: Region) -> Self {
+        Self {
+            frame_duration: Duration::from_secs_f64(1. / region.frame_rate()),
+            next_frame: Instant::now(),
+            fast_forward: false,
+            max_frame_skip: 4,
+        }
+    }
+
+    pub fn frame_duration(&self) -> Duration {
+        self.frame_duration
+    }
+
+    /// When true, [`FramePacer::wait_for_next_frame`] doesn't sleep
+    pub fn set_fast_forward(&mut self, fast_forward: bool) {
+        self.fast_forward = fast_forward;
+    }
+
+    pub fn set_max_frame_skip(&mut self, max_frame_skip: u32) {
+        self.max_frame_skip = max_frame_skip;
+    }
+
+    /// Sleeps until the next frame is due.
+    /// When running behind, returns how many frames should be emulated without being presented
+    /// to catch up.
+    pub fn wait_for_next_frame(&mut self) -> u32 {
+        let now = Instant::now();
+        if self.fast_forward {
+            self.next_frame = now;
+            return 0;
+        }
+
+        self.next_frame += self.frame_duration;
+        if self.next_frame > now {
+            thread::sleep(self.next_frame - now);
+            return 0;
+        }
+
+        let behind =
+            ((now - self.next_frame).as_secs_f64() / self.frame_duration.as_secs_f64()) as u32;
+        if behind > self.max_frame_skip {
+            self.next_frame = now;
+            return 0;
+        }
+
+        // The skipped frames are accounted for now, the caller won't call this function for them
+        self.next_frame += self.frame_duration * behind;
+        behind
+    }
+}
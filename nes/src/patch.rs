@@ -0,0 +1,179 @@
+use crate::NesError;
+
+/// Applies an IPS or a BPS patch (detected by its magic number) to a whole ROM file
+pub(crate) fn apply_patch(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, NesError> {
+    if let Some(records) = patch.strip_prefix(b"PATCH") {
+        apply_ips(rom, records)
+    } else if patch.starts_with(b"BPS1") {
+        apply_bps(rom, patch)
+    } else {
+        Err(NesError::InvalidPatch)
+    }
+}
+
+/// https://zerosoft.zophar.net/ips.php
+fn apply_ips(rom: &[u8], mut records: &[u8]) -> Result<Vec<u8>, NesError> {
+    let mut target = rom.to_vec();
+
+    loop {
+        let offset = take(&mut records, 3)?;
+        if offset == b"EOF" {
+            break;
+        }
+        let offset = big_endian(offset);
+        let size = big_endian(take(&mut records, 2)?);
+
+        // Records can write past the end of the ROM
+        if size == 0 {
+            let rle = take(&mut records, 3)?;
+            write_at(&mut target, offset, &vec![rle[2]; big_endian(&rle[..2])]);
+        } else {
+            write_at(&mut target, offset, take(&mut records, size)?);
+        }
+    }
+
+    // Optional truncation extension
+    if let Ok(size) = take(&mut records, 3) {
+        target.truncate(big_endian(size));
+    }
+
+    Ok(target)
+}
+
+/// https://www.romhacking.net/documents/746/
+fn apply_bps(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, NesError> {
+    // The magic number, then the actions and the 3 CRCs
+    let footer_start = patch
+        .len()
+        .checked_sub(12)
+        .filter(|&start| start >= 4)
+        .ok_or(NesError::InvalidPatch)?;
+    let (body, footer) = patch.split_at(footer_start);
+    let footer_crc =
+        |i: usize| u32::from_le_bytes([footer[i], footer[i + 1], footer[i + 2], footer[i + 3]]);
+
+    if crc32(&patch[..patch.len() - 4]) != footer_crc(8) {
+        return Err(NesError::InvalidPatch);
+    }
+    if crc32(rom) != footer_crc(0) {
+        return Err(NesError::PatchMismatch);
+    }
+
+    let mut actions = &body[4..];
+    let source_size = bps_number(&mut actions)?;
+    let target_size = bps_number(&mut actions)?;
+    let metadata_size = bps_number(&mut actions)?;
+    take(&mut actions, metadata_size)?;
+
+    if source_size != rom.len() {
+        return Err(NesError::PatchMismatch);
+    }
+
+    let mut target = Vec::with_capacity(target_size);
+    let (mut source_offset, mut target_offset) = (0usize, 0usize);
+    while !actions.is_empty() {
+        let action = bps_number(&mut actions)?;
+        let length = (action >> 2) + 1;
+
+        match action & 3 {
+            // Source read
+            0 => {
+                let start = target.len();
+                let bytes = rom
+                    .get(start..start + length)
+                    .ok_or(NesError::InvalidPatch)?;
+                target.extend_from_slice(bytes);
+            }
+            // Target read
+            1 => target.extend_from_slice(take(&mut actions, length)?),
+            // Source copy
+            2 => {
+                source_offset = bps_offset(&mut actions, source_offset)?;
+                let bytes = rom
+                    .get(source_offset..source_offset + length)
+                    .ok_or(NesError::InvalidPatch)?;
+                target.extend_from_slice(bytes);
+                source_offset += length;
+            }
+            // Target copy, byte by byte since the copy can overlap the bytes it writes
+            _ => {
+                target_offset = bps_offset(&mut actions, target_offset)?;
+                for _ in 0..length {
+                    let byte = *target.get(target_offset).ok_or(NesError::InvalidPatch)?;
+                    target.push(byte);
+                    target_offset += 1;
+                }
+            }
+        }
+    }
+
+    if target.len() != target_size || crc32(&target) != footer_crc(4) {
+        return Err(NesError::PatchMismatch);
+    }
+
+    Ok(target)
+}
+
+/// Variable length number, 7 bits per byte with an implicit increment
+fn bps_number(data: &mut &[u8]) -> Result<usize, NesError> {
+    let (mut number, mut shift) = (0usize, 1usize);
+    loop {
+        let byte = take(data, 1)?[0];
+        number = usize::from(byte & 0x7F)
+            .checked_mul(shift)
+            .and_then(|n| n.checked_add(number))
+            .ok_or(NesError::InvalidPatch)?;
+        if byte & 0x80 != 0 {
+            return Ok(number);
+        }
+        shift = shift.checked_mul(0x80).ok_or(NesError::InvalidPatch)?;
+        number = number.checked_add(shift).ok_or(NesError::InvalidPatch)?;
+    }
+}
+
+/// Moves `offset` by a signed number, the sign is in bit 0
+fn bps_offset(data: &mut &[u8], offset: usize) -> Result<usize, NesError> {
+    let number = bps_number(data)?;
+    let moved = if number & 1 != 0 {
+        offset.checked_sub(number >> 1)
+    } else {
+        offset.checked_add(number >> 1)
+    };
+    moved.ok_or(NesError::InvalidPatch)
+}
+
+fn take<'a>(data: &mut &'a [u8], len: usize) -> Result<&'a [u8], NesError> {
+    if data.len() < len {
+        return Err(NesError::InvalidPatch);
+    }
+    let (bytes, rest) = data.split_at(len);
+    *data = rest;
+    Ok(bytes)
+}
+
+fn big_endian(bytes: &[u8]) -> usize {
+    bytes
+        .iter()
+        .fold(0, |number, &byte| number << 8 | usize::from(byte))
+}
+
+fn write_at(target: &mut Vec<u8>, offset: usize, bytes: &[u8]) {
+    let end = offset + bytes.len();
+    if target.len() < end {
+        target.resize(end, 0);
+    }
+    target[offset..end].copy_from_slice(bytes);
+}
+
+/// CRC-32 (ISO-HDLC), as used by BPS
+pub(crate) fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0, |crc, &byte| {
+        (0..8).fold(crc ^ u32::from(byte), |crc, _| {
+            if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            }
+        })
+    })
+}
@@ -1,6 +1,13 @@
+use std::mem;
+
 use serde::{Deserialize, Serialize};
 
-use super::Nes;
+use super::{snapshot::PpuSnapshot, Nes, NesError};
+
+mod debug;
+
+use debug::DebugCache;
+pub use debug::{NAMETABLE_HEIGHT, NAMETABLE_WIDTH, PATTERN_TABLE_SIZE};
 
 /// This pallete maps the PPU output to RGB (24 bits RGB format)
 pub static PALETTE: [u8; 192] = [
@@ -74,6 +81,9 @@ pub struct Ppu {
 
     sprite_index: u8,
     sprite_buffer: Vec<Sprite>,
+    /// Sprites over the limit of 8 per scanline, only used when the limit is disabled
+    extra_sprites: Vec<Sprite>,
+    sprite_limit: bool,
     sprite_cache: Vec<bool>,
 
     vram_addr: usize,
@@ -83,8 +93,21 @@ pub struct Ppu {
     pub xpos: u16,
     pub scanline: u16,
     pub cycle_count: u32,
+    /// Dots since power-on, unlike `cycle_count` this doesn't wrap
+    dot_count: u64,
     odd_frame: bool,
 
+    /// Whether pixels get written to the output buffer
+    pub(crate) output_enabled: bool,
+    /// Number of frames that aren't drawn after each drawn frame
+    frame_skip: u32,
+    skipped_frames: u32,
+    skip_frame: bool,
+    /// Pixels have been written to the output buffer during the current frame
+    pixels_written: bool,
+    /// Same for the last completed frame
+    buffer_changed: bool,
+
     nametable_byte: u8,
     attribute: u8,
     tile_addr: usize,
@@ -94,6 +117,8 @@ pub struct Ppu {
     shift_high: u16,
 
     ignore_writes: bool,
+    /// Set by a warm reset, writes are accepted again at the end of the next vblank
+    reset_pending: bool,
 
     ppustatus: u8,
     pub oamaddr: u8,
@@ -123,6 +148,9 @@ pub struct Ppu {
     emphasize_red: bool,
     emphasize_green: bool,
     emphasize_blue: bool,
+
+    #[serde(skip)]
+    pub(crate) debug_cache: DebugCache,
 }
 
 impl Ppu {
@@ -152,6 +180,8 @@ impl Ppu {
 
             sprite_index: 0,
             sprite_buffer: vec![Sprite::new(); 8],
+            extra_sprites: Vec::new(),
+            sprite_limit: true,
             sprite_cache: vec![false; 0x101],
 
             vram_addr: 0,
@@ -161,8 +191,16 @@ impl Ppu {
             xpos: 0,
             scanline: 0,
             cycle_count: 0,
+            dot_count: 0,
             odd_frame: false,
 
+            output_enabled: true,
+            frame_skip: 0,
+            skipped_frames: 0,
+            skip_frame: false,
+            pixels_written: false,
+            buffer_changed: false,
+
             nametable_byte: 0,
             attribute: 0,
             tile_addr: 0,
@@ -172,6 +210,7 @@ impl Ppu {
             shift_high: 0,
 
             ignore_writes: true,
+            reset_pending: false,
 
             ppustatus: 0,
             oamaddr: 0,
@@ -198,11 +237,34 @@ impl Ppu {
             emphasize_red: false,
             emphasize_green: false,
             emphasize_blue: false,
+
+            debug_cache: DebugCache::default(),
         }
     }
 }
 
 impl Nes {
+    /// The last frame as a binary PPM (P6) image
+    pub(crate) fn ppu_frame_ppm(&self) -> Vec<u8> {
+        let mut ppm = b"P6\n256 240\n255\n".to_vec();
+        for &color in &self.ppu.output_buffer {
+            let color = usize::from(color & 0x3F) * 3;
+            ppm.extend_from_slice(&PALETTE[color..color + 3]);
+        }
+        ppm
+    }
+
+    /// Nearest-neighbor upscale of the last frame, `dst` must be exactly the scaled size
+    pub(crate) fn ppu_scale_frame(&self, scale: usize, dst: &mut [u8]) {
+        let width = 256 * scale;
+        for (y, row) in dst.chunks_exact_mut(width).enumerate() {
+            let src = &self.ppu.output_buffer[(y / scale) * 256..][..256];
+            for (x, pixel) in row.iter_mut().enumerate() {
+                *pixel = src[x / scale];
+            }
+        }
+    }
+
     #[inline]
     fn ppu_write(&mut self, mut addr: usize, val: u8) {
         addr &= 0x3FFF;
@@ -211,72 +273,41 @@ impl Nes {
             .notify_a12(addr, self.ppu.cycle_count, &mut self.cpu.irq_signal);
 
         match addr {
-            0..=0x1FFF => self.mapper.write_chr(addr, val),
-            0x2000..=0x3EFF => self.write_nametable(addr & 0xFFF, val),
-            0x3F00..=0x3FFF => self.palette_write(addr, val),
+            0..=0x1FFF => {
+                self.mapper.write_chr(addr, val);
+                self.ppu.debug_cache.invalidate_chr();
+            }
+            0x2000..=0x3EFF => {
+                self.write_nametable(addr & 0xFFF, val);
+                self.ppu.debug_cache.invalidate_nametables();
+            }
+            0x3F00..=0x3FFF => {
+                self.palette_write(addr, val);
+                self.ppu.debug_cache.invalidate_chr();
+            }
             _ => unreachable!(),
         }
     }
 
+    /// Maps a nametable address ($000 - $FFF) to nametable RAM. The mirroring is looked up on
+    /// every access, so mirroring changes take effect immediately, even mid-frame.
     #[inline]
-    fn write_nametable(&mut self, addr: usize, val: u8) {
+    fn mirror_nametable(&self, addr: usize) -> usize {
         match self.mapper.mirroring() {
-            Mirroring::Vertical => match addr {
-                0..=0x3FF => {
-                    self.mapper.write_nametable(addr, val);
-                    self.mapper.write_nametable(addr + 0x800, val);
-                }
-                0x400..=0x7FF => {
-                    self.mapper.write_nametable(addr, val);
-                    self.mapper.write_nametable(addr + 0x800, val);
-                }
-                0x800..=0xBFF => {
-                    self.mapper.write_nametable(addr, val);
-                    self.mapper.write_nametable(addr - 0x400, val);
-                }
-                0xC00..=0xFFF => {
-                    self.mapper.write_nametable(addr, val);
-                    self.mapper.write_nametable(addr - 0x800, val);
-                }
-                _ => unreachable!(),
-            },
-            Mirroring::Horizontal => match addr {
-                0..=0x3FF => {
-                    self.mapper.write_nametable(addr, val);
-                    self.mapper.write_nametable(addr + 0x400, val);
-                }
-                0x400..=0x7FF => {
-                    self.mapper.write_nametable(addr, val);
-                    self.mapper.write_nametable(addr - 0x400, val);
-                }
-                0x800..=0xBFF => {
-                    self.mapper.write_nametable(addr, val);
-                    self.mapper.write_nametable(addr + 0x400, val);
-                }
-                0xC00..=0xFFF => {
-                    self.mapper.write_nametable(addr, val);
-                    self.mapper.write_nametable(addr - 0x400, val);
-                }
-                _ => unreachable!(),
-            },
-            Mirroring::SingleScreenLow => match addr {
-                0..=0x3FF => self.mapper.write_nametable(addr, val),
-                0x400..=0x7FF => self.mapper.write_nametable(addr - 0x400, val),
-                0x800..=0xBFF => self.mapper.write_nametable(addr - 0x800, val),
-                0xC00..=0xFFF => self.mapper.write_nametable(addr - 0xC00, val),
-                _ => unreachable!(),
-            },
-            Mirroring::SingleScreenHigh => match addr {
-                0..=0x3FF => self.mapper.write_nametable(addr + 0x400, val),
-                0x400..=0x7FF => self.mapper.write_nametable(addr, val),
-                0x800..=0xBFF => self.mapper.write_nametable(addr - 0x400, val),
-                0xC00..=0xFFF => self.mapper.write_nametable(addr - 0x800, val),
-                _ => unreachable!(),
-            },
-            Mirroring::FourScreen => self.mapper.write_nametable(addr, val),
+            Mirroring::Vertical => addr & 0x7FF,
+            Mirroring::Horizontal => ((addr >> 1) & 0x400) | (addr & 0x3FF),
+            Mirroring::SingleScreenLow => addr & 0x3FF,
+            Mirroring::SingleScreenHigh => 0x400 | (addr & 0x3FF),
+            Mirroring::FourScreen => addr,
         }
     }
 
+    #[inline]
+    fn write_nametable(&mut self, addr: usize, val: u8) {
+        let addr = self.mirror_nametable(addr);
+        self.mapper.write_nametable(addr, val);
+    }
+
     #[inline]
     fn ppu_read(&mut self, mut addr: usize) -> u8 {
         addr &= 0x3FFF;
@@ -293,26 +324,8 @@ impl Nes {
     }
 
     #[inline]
-    fn read_nametable(&mut self, addr: usize) -> u8 {
-        match self.mapper.mirroring() {
-            Mirroring::Vertical => self.mapper.read_nametable(addr),
-            Mirroring::Horizontal => self.mapper.read_nametable(addr),
-            Mirroring::SingleScreenLow => match addr {
-                0..=0x3FF => self.mapper.read_nametable(addr),
-                0x400..=0x7FF => self.mapper.read_nametable(addr - 0x400),
-                0x800..=0xBFF => self.mapper.read_nametable(addr - 0x800),
-                0xC00..=0xFFF => self.mapper.read_nametable(addr - 0xC00),
-                _ => unreachable!(),
-            },
-            Mirroring::SingleScreenHigh => match addr {
-                0..=0x3FF => self.mapper.read_nametable(addr + 0x400),
-                0x400..=0x7FF => self.mapper.read_nametable(addr),
-                0x800..=0xBFF => self.mapper.read_nametable(addr - 0x400),
-                0xC00..=0xFFF => self.mapper.read_nametable(addr - 0x800),
-                _ => unreachable!(),
-            },
-            Mirroring::FourScreen => self.mapper.read_nametable(addr),
-        }
+    fn read_nametable(&self, addr: usize) -> u8 {
+        self.mapper.read_nametable(self.mirror_nametable(addr))
     }
 
     #[inline]
@@ -362,6 +375,7 @@ impl Nes {
     #[inline]
     pub(crate) fn ppu_read_reg(&mut self, addr: usize) -> u8 {
         match addr & 7 {
+            // Write-only registers return the open bus latch
             0 | 1 | 3 | 5 | 6 => (),
             2 => self.read_ppustatus(),
             4 => self.read_oamdata(),
@@ -392,6 +406,29 @@ impl Nes {
         self.ppu.ignore_writes = false;
     }
 
+    #[inline]
+    pub(crate) fn ppu_writes_enabled(&self) -> bool {
+        !self.ppu.ignore_writes
+    }
+
+    /// The reset line of the PPU: PPUCTRL, PPUMASK, the scroll, the write toggle, the read buffer
+    /// and the odd frame flag are cleared, and writes to $2000, $2001, $2005 and $2006 are
+    /// ignored until the end of the next vblank. PPUSTATUS, OAMADDR, the VRAM address, OAM and
+    /// the palettes keep their values.
+    pub(crate) fn ppu_reset(&mut self) {
+        self.ppu.latch = 0;
+        self.write_ppuctrl();
+        self.write_ppumask();
+        self.ppu.temp_vram_addr = 0;
+        self.ppu.x_fine_scroll = 0;
+        self.ppu.write_toggle = false;
+        self.ppu.read_buffer = 0;
+        self.ppu.odd_frame = false;
+
+        self.ppu.ignore_writes = true;
+        self.ppu.reset_pending = true;
+    }
+
     /** Ppuctrl
     N -- 00000011 -- Name table address (0 = 0x2000; 1 = 0x2400; 2 = 0x2800; 3 = 0x2C00)
     I -- 00000100 -- PPU address increment (0: add 1, going across; 1: add 32, going down)
@@ -416,7 +453,11 @@ impl Nes {
         };
         self.ppu.addr_increment = if val & (1 << 2) == 0 { 1 } else { 32 };
         self.ppu.sp_pattern_table_addr = if val & (1 << 3) == 0 { 0 } else { 0x1000 };
-        self.ppu.bg_pattern_table_addr = if val & (1 << 4) == 0 { 0 } else { 0x1000 };
+        let bg_pattern_table_addr = if val & (1 << 4) == 0 { 0 } else { 0x1000 };
+        if bg_pattern_table_addr != self.ppu.bg_pattern_table_addr {
+            self.ppu.bg_pattern_table_addr = bg_pattern_table_addr;
+            self.ppu.debug_cache.invalidate_nametables();
+        }
         self.ppu.sp_size = if val & (1 << 5) == 0 { 8 } else { 16 };
 
         self.ppu.nmi_on_vblank = val & (1 << 7) != 0;
@@ -545,9 +586,7 @@ impl Nes {
 
         if (self.ppu.vram_addr & 0x3FFF) >= 0x3F00 {
             self.ppu.latch = self.palette_read(self.ppu.vram_addr);
-            self.ppu.read_buffer = self
-                .mapper
-                .read_nametable((self.ppu.vram_addr & 0x3FFF) - 0x3000);
+            self.ppu.read_buffer = self.read_nametable((self.ppu.vram_addr & 0x3FFF) - 0x3000);
         }
 
         if self.ppu.rendering_enabled && (self.ppu.scanline < 240 || self.ppu.scanline == 261) {
@@ -607,9 +646,16 @@ impl Nes {
             if self.ppu.scanline > 261 {
                 self.ppu.scanline = 0;
             }
+
+            if self.ppu.scanline == 240 {
+                let (dots, region) = (u64::from(self.overclock_scanlines) * 341, self.region);
+                self.overclock_cycles =
+                    (dots * region.ppu_clock_divider() / region.master_clock_divider()) as u32;
+            }
         }
 
         self.ppu.cycle_count = self.ppu.cycle_count.wrapping_add(1);
+        self.ppu.dot_count += 1;
     }
 
     /// https://wiki.nesdev.org/w/index.php/PPU_rendering
@@ -704,10 +750,7 @@ impl Nes {
                         );
                     }
                     1 => {
-                        self.ppu.ppustatus &= !0xE0;
-                        self.cpu.nmi_signal = false;
-                        self.ppu.suppress_nmi = false;
-                        self.ppu.prev_nmi = false;
+                        self.pre_render_clear_flags();
                         self.fetch_nt();
                         self.oam_refresh_bug();
                     }
@@ -721,12 +764,14 @@ impl Nes {
                     339 => {
                         self.ppu.sprite_cache.fill(false);
                         self.frame_ready = true;
+                        self.ppu.buffer_changed = mem::take(&mut self.ppu.pixels_written);
+                        self.ppu_advance_frame_skip();
                         self.ppu_read(self.nametable_addr());
 
                         // The skipped tick is implemented by jumping directly from (339, 261)
                         // to (0, 0), meaning the last tick of the last NT fetch takes place at (0, 0)
                         // on odd frames replacing the idle tick
-                        if self.ppu.odd_frame & self.ppu.show_bg {
+                        if self.ppu.odd_frame && self.ppu.rendering_enabled {
                             self.ppu.xpos = 340;
                         }
 
@@ -739,6 +784,22 @@ impl Nes {
         }
     }
 
+    /// Clears VBlank, sprite 0 hit and sprite overflow at dot 1 of the pre-render scanline.
+    /// Unlike a $2002 read, which only clears the VBlank flag, this clears all three flags
+    /// and re-arms the NMI logic for the next frame.
+    #[inline]
+    fn pre_render_clear_flags(&mut self) {
+        self.ppu.ppustatus &= !0xE0;
+        self.cpu.nmi_signal = false;
+        self.ppu.suppress_nmi = false;
+        self.ppu.prev_nmi = false;
+
+        if self.ppu.reset_pending {
+            self.ppu.reset_pending = false;
+            self.ppu.ignore_writes = false;
+        }
+    }
+
     #[inline]
     fn vblank(&mut self) {
         match (self.ppu.scanline, self.ppu.xpos) {
@@ -754,6 +815,7 @@ impl Nes {
             }
             (241, 0) => (),
             _ => {
+                // Enabling NMIs while the flag is set fires an NMI, after every 0 to 1 transition
                 let current_nmi = self.ppu.nmi_on_vblank
                     && ((self.ppu.ppustatus & 0x80) != 0)
                     && !self.ppu.suppress_nmi;
@@ -847,6 +909,7 @@ impl Nes {
     fn fetch_sprites(&mut self) {
         if self.ppu.xpos == 257 {
             self.ppu.sprite_cache.fill(false);
+            self.ppu.extra_sprites.clear();
             self.ppu.sprite_index = 0;
         }
 
@@ -862,15 +925,36 @@ impl Nes {
                 3 => self.load_sprite(),
                 _ => (),
             };
+
+            if self.ppu.xpos == 320 {
+                self.load_extra_sprites();
+            }
         }
     }
 
     #[inline]
     fn load_sprite(&mut self) {
         let sprite_addr = 4 * self.ppu.sprite_index as usize;
-        let sprite = &mut self.ppu.sprite_buffer[self.ppu.sprite_index as usize];
-        sprite.y = self.ppu.secondary_oam[sprite_addr];
-        sprite.x = self.ppu.secondary_oam[sprite_addr + 3];
+        let mut oam_entry = [0; 4];
+        oam_entry.copy_from_slice(&self.ppu.secondary_oam[sprite_addr..sprite_addr + 4]);
+
+        let mut sprite = self.decode_sprite(oam_entry);
+        let index = sprite.index as usize;
+        sprite.tile_low = self.ppu_read(index);
+        // The second read is performed 2 cycles later, but it shouldn't have any efect
+        sprite.tile_high = self.ppu_read(index + 8);
+
+        self.ppu.sprite_buffer[self.ppu.sprite_index as usize] = sprite;
+        self.ppu.sprite_index = (self.ppu.sprite_index + 1) & 7;
+    }
+
+    /// Decodes a sprite for the next scanline and marks its pixels in the sprite cache.
+    /// The tile data isn't fetched.
+    #[inline]
+    fn decode_sprite(&mut self, oam_entry: [u8; 4]) -> Sprite {
+        let mut sprite = Sprite::new();
+        sprite.y = oam_entry[0];
+        sprite.x = oam_entry[3];
 
         for i in 1..9 {
             if sprite.x as usize + i < 257 {
@@ -878,7 +962,7 @@ impl Nes {
             }
         }
 
-        let attributes = self.ppu.secondary_oam[sprite_addr + 2];
+        let attributes = oam_entry[2];
         sprite.vertical_flip = attributes & 0x80 != 0;
         sprite.horizontal_flip = attributes & 0x40 != 0;
         sprite.priority = attributes & 0x20 != 0;
@@ -896,7 +980,7 @@ impl Nes {
             scanline - sprite.y as i16
         };
 
-        let index = self.ppu.secondary_oam[sprite_addr + 1];
+        let index = oam_entry[1];
         sprite.index = if self.ppu.sp_size == 8 {
             (self.ppu.sp_pattern_table_addr as u16 | (u16::from(index) << 4))
                 .wrapping_add(y_offset as u16)
@@ -909,12 +993,56 @@ impl Nes {
             pattern_table_addr | (u16::from(index & !1) << 4).wrapping_add(y_offset as u16)
         };
 
-        let index = sprite.index as usize;
-        self.ppu.sprite_buffer[self.ppu.sprite_index as usize].tile_low = self.ppu_read(index);
-        // The second read is performed 2 cycles later, but it shouldn't have any efect
-        self.ppu.sprite_buffer[self.ppu.sprite_index as usize].tile_high = self.ppu_read(index + 8);
+        sprite
+    }
 
-        self.ppu.sprite_index = (self.ppu.sprite_index + 1) & 7;
+    pub(crate) fn ppu_sprites_on_scanline(&self, line: u16) -> u8 {
+        // Sprites are evaluated on the scanline before the one they're drawn on
+        let Some(scanline) = line.checked_sub(1) else {
+            return 0;
+        };
+
+        let sp_size = u16::from(self.ppu.sp_size);
+        self.ppu
+            .oam
+            .chunks_exact(4)
+            .filter(|entry| {
+                let y = u16::from(entry[0]);
+                scanline >= y && scanline < y + sp_size
+            })
+            .count() as u8
+    }
+
+    /// With the sprite limit disabled, the sprites that didn't fit into secondary OAM are
+    /// rendered too. They are looked up after the real sprite fetches, so the evaluation,
+    /// the overflow flag and the mapper-visible PPU reads stay the same.
+    fn load_extra_sprites(&mut self) {
+        self.ppu.extra_sprites.clear();
+        if self.ppu.sprite_limit || self.ppu.scanline == 261 {
+            return;
+        }
+
+        let scanline = self.ppu.scanline;
+        let sp_size = u16::from(self.ppu.sp_size);
+        let mut in_range = 0;
+        for n in 0..64 {
+            let mut oam_entry = [0; 4];
+            oam_entry.copy_from_slice(&self.ppu.oam[4 * n..4 * n + 4]);
+
+            let y = u16::from(oam_entry[0]);
+            if scanline < y || scanline >= y + sp_size {
+                continue;
+            }
+
+            in_range += 1;
+            if in_range > 8 {
+                let mut sprite = self.decode_sprite(oam_entry);
+                let index = sprite.index as usize & 0x1FFF;
+                sprite.tile_low = self.mapper.read_chr(index);
+                sprite.tile_high = self.mapper.read_chr((index + 8) & 0x1FFF);
+                self.ppu.extra_sprites.push(sprite);
+            }
+        }
     }
 
     /// http://wiki.nesdev.org/w/index.php/PPU_sprite_evaluation
@@ -1092,11 +1220,88 @@ impl Nes {
         self.ppu.attribute >>= 2;
     }
 
+    pub(crate) fn ppu_snapshot(&self) -> PpuSnapshot {
+        PpuSnapshot {
+            scanline: self.ppu.scanline,
+            xpos: self.ppu.xpos,
+            odd_frame: self.ppu.odd_frame,
+            status: self.ppu.ppustatus & 0xE0,
+            vram_addr: self.ppu.vram_addr as u16,
+            temp_vram_addr: self.ppu.temp_vram_addr as u16,
+            fine_x: self.ppu.x_fine_scroll,
+            write_toggle: self.ppu.write_toggle,
+            rendering_enabled: self.ppu.rendering_enabled,
+        }
+    }
+
+    /// Decides whether the next frame gets drawn
+    fn ppu_advance_frame_skip(&mut self) {
+        if self.ppu.skipped_frames >= self.ppu.frame_skip {
+            self.ppu.skipped_frames = 0;
+            self.ppu.skip_frame = false;
+        } else {
+            self.ppu.skipped_frames += 1;
+            self.ppu.skip_frame = true;
+        }
+    }
+
+    pub(crate) fn ppu_odd_frame(&self) -> bool {
+        self.ppu.odd_frame
+    }
+
+    pub(crate) fn ppu_total_dots(&self) -> u64 {
+        self.ppu.dot_count
+    }
+
+    pub(crate) fn ppu_scanline_and_dot(&self) -> (u16, u16) {
+        (self.ppu.scanline, self.ppu.xpos)
+    }
+
+    pub(crate) fn ppu_set_scanline_and_dot(
+        &mut self,
+        scanline: u16,
+        dot: u16,
+    ) -> Result<(), NesError> {
+        if scanline > 261 || dot > 340 {
+            return Err(NesError::InvalidPpuPosition { scanline, dot });
+        }
+
+        self.ppu.scanline = scanline;
+        self.ppu.xpos = dot;
+        Ok(())
+    }
+
+    pub(crate) fn ppu_set_sprite_limit(&mut self, enabled: bool) {
+        self.ppu.sprite_limit = enabled;
+    }
+
+    pub(crate) fn ppu_sprite_limit(&self) -> bool {
+        self.ppu.sprite_limit
+    }
+
+    pub(crate) fn ppu_take_frame_buffer_changed(&mut self) -> bool {
+        mem::take(&mut self.ppu.buffer_changed)
+    }
+
+    pub(crate) fn ppu_frame_skip(&self) -> u32 {
+        self.ppu.frame_skip
+    }
+
+    pub(crate) fn ppu_set_frame_skip(&mut self, frame_skip: u32) {
+        self.ppu.frame_skip = frame_skip;
+        self.ppu.skipped_frames = 0;
+        self.ppu.skip_frame = false;
+    }
+
     #[inline(always)]
     fn draw_pixel(&mut self) {
         let addr = (usize::from(self.ppu.scanline) << 8) + usize::from(self.ppu.xpos - 1);
+        // The pixel color is still needed for sprite 0 hit detection
         let color_index = self.pixel_color();
-        self.ppu.output_buffer[addr] = self.ppu.palettes[color_index];
+        if self.ppu.output_enabled && !self.ppu.skip_frame {
+            self.ppu.output_buffer[addr] = self.ppu.palettes[color_index];
+            self.ppu.pixels_written = true;
+        }
     }
 
     #[inline(always)]
@@ -1125,8 +1330,10 @@ impl Nes {
             && self.ppu.show_sp
             && self.ppu.xpos > self.ppu.sp_left_clip as u16
         {
-            for i in 0..self.ppu.sprite_count {
-                let spr = &mut self.ppu.sprite_buffer[i as usize];
+            let sprites = self.ppu.sprite_buffer[..self.ppu.sprite_count as usize]
+                .iter()
+                .chain(&self.ppu.extra_sprites);
+            for (i, spr) in sprites.enumerate() {
                 let shift = self.ppu.xpos as i32 - spr.x as i32 - 1;
                 if shift >= 0 && shift <= 7 {
                     let sp_color = if spr.horizontal_flip {
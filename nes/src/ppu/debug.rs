@@ -0,0 +1,180 @@
+use super::PALETTE;
+use crate::Nes;
+
+/// Width and height of a decoded pattern table in pixels
+pub const PATTERN_TABLE_SIZE: usize = 128;
+pub const NAMETABLE_WIDTH: usize = 256;
+pub const NAMETABLE_HEIGHT: usize = 240;
+/// Brightness of the other two channels when a color is emphasized
+const EMPHASIS_ATTENUATION: f32 = 0.816;
+
+/// Decoded debug images, they are only regenerated after the memory they're decoded from changes
+#[derive(Default)]
+pub(crate) struct DebugCache {
+    pattern_tables: [Option<Vec<u8>>; 2],
+    nametables: [Option<Vec<u8>>; 4],
+    /// Drawn instead of the backdrop color in the nametable images
+    pub(crate) backdrop: Option<[u8; 3]>,
+}
+
+impl DebugCache {
+    /// CHR memory, CHR banks or the palettes have changed
+    #[inline]
+    pub(crate) fn invalidate_chr(&mut self) {
+        self.pattern_tables = Default::default();
+        self.invalidate_nametables();
+    }
+
+    #[inline]
+    pub(crate) fn invalidate_nametables(&mut self) {
+        self.nametables = Default::default();
+    }
+
+    pub(crate) fn pattern_table_cached(&self, table: usize) -> bool {
+        self.pattern_tables[table].is_some()
+    }
+
+    pub(crate) fn nametable_cached(&self, index: usize) -> bool {
+        self.nametables[index].is_some()
+    }
+}
+
+impl Nes {
+    pub(crate) fn ppu_set_debug_backdrop(&mut self, color: Option<[u8; 3]>) {
+        self.ppu.debug_cache.backdrop = color;
+        self.ppu.debug_cache.invalidate_nametables();
+    }
+
+    /// 128x128 RGB image of a pattern table ($0000 or $1000), drawn with the first background palette
+    pub(crate) fn ppu_debug_pattern_table(&mut self, table: usize) -> &[u8] {
+        if !self.ppu.debug_cache.pattern_table_cached(table) {
+            let image = self.decode_pattern_table(table);
+            self.ppu.debug_cache.pattern_tables[table] = Some(image);
+        }
+
+        self.ppu.debug_cache.pattern_tables[table]
+            .as_deref()
+            .unwrap()
+    }
+
+    /// 256x240 RGB image of a nametable ($2000, $2400, $2800 or $2C00), drawn with
+    /// the current background pattern table and mirroring
+    pub(crate) fn ppu_debug_nametable(&mut self, index: usize) -> &[u8] {
+        if !self.ppu.debug_cache.nametable_cached(index) {
+            let image = self.decode_nametable(index);
+            self.ppu.debug_cache.nametables[index] = Some(image);
+        }
+
+        self.ppu.debug_cache.nametables[index].as_deref().unwrap()
+    }
+
+    /// Palette RAM in RGB, the 4 background palettes then the 4 sprite palettes
+    pub(crate) fn ppu_debug_palettes(&self) -> [[[u8; 3]; 4]; 8] {
+        let emphasis = [
+            self.ppu.emphasize_red,
+            self.ppu.emphasize_green,
+            self.ppu.emphasize_blue,
+        ];
+
+        let mut palettes = [[[0; 3]; 4]; 8];
+        for (i, rgb) in palettes.iter_mut().flatten().enumerate() {
+            let mut index = self.ppu.palettes[i];
+            if self.ppu.greyscale {
+                index &= 0x30;
+            }
+
+            let color = usize::from(index) * 3;
+            for (channel, value) in rgb.iter_mut().enumerate() {
+                *value = PALETTE[color + channel];
+                // Emphasizing a color darkens the other two
+                let dimmed = (0..3).any(|other| other != channel && emphasis[other]);
+                if dimmed {
+                    *value = (f32::from(*value) * EMPHASIS_ATTENUATION) as u8;
+                }
+            }
+        }
+
+        palettes
+    }
+
+    fn decode_pattern_table(&mut self, table: usize) -> Vec<u8> {
+        let mut image = vec![0; PATTERN_TABLE_SIZE * PATTERN_TABLE_SIZE * 3];
+
+        for tile in 0..256 {
+            let x = (tile % 16) * 8;
+            let y = (tile / 16) * 8;
+            self.draw_tile(
+                &mut image,
+                PATTERN_TABLE_SIZE,
+                (x, y),
+                table * 0x1000 + tile * 16,
+                0,
+                None,
+            );
+        }
+
+        image
+    }
+
+    fn decode_nametable(&mut self, index: usize) -> Vec<u8> {
+        let mut image = vec![0; NAMETABLE_WIDTH * NAMETABLE_HEIGHT * 3];
+        let base = index * 0x400;
+        let backdrop = self.ppu.debug_cache.backdrop;
+
+        for tile_y in 0..30 {
+            for tile_x in 0..32 {
+                let tile = usize::from(self.read_nametable(base + tile_y * 32 + tile_x));
+
+                let attr_addr = base + 0x3C0 + (tile_y / 4) * 8 + tile_x / 4;
+                let shift = ((tile_y & 2) << 1) | (tile_x & 2);
+                let palette = (self.read_nametable(attr_addr) >> shift) & 3;
+
+                let tile_addr = self.ppu.bg_pattern_table_addr + tile * 16;
+                self.draw_tile(
+                    &mut image,
+                    NAMETABLE_WIDTH,
+                    (tile_x * 8, tile_y * 8),
+                    tile_addr,
+                    palette,
+                    backdrop,
+                );
+            }
+        }
+
+        image
+    }
+
+    fn draw_tile(
+        &self,
+        image: &mut [u8],
+        width: usize,
+        (x, y): (usize, usize),
+        tile_addr: usize,
+        palette: u8,
+        backdrop: Option<[u8; 3]>,
+    ) {
+        for row in 0..8 {
+            let low = self.mapper.read_chr(tile_addr + row);
+            let high = self.mapper.read_chr(tile_addr + row + 8);
+
+            for col in 0..8 {
+                let bit = 7 - col;
+                let color = (((high >> bit) & 1) << 1) | ((low >> bit) & 1);
+                let pixel = ((y + row) * width + x + col) * 3;
+
+                if let (0, Some(rgb)) = (color, backdrop) {
+                    image[pixel..pixel + 3].copy_from_slice(&rgb);
+                    continue;
+                }
+
+                let palette_index = if color == 0 {
+                    0
+                } else {
+                    usize::from(palette * 4 + color)
+                };
+                let nes_color = usize::from(self.ppu.palettes[palette_index]);
+                image[pixel..pixel + 3].copy_from_slice(&PALETTE[nes_color * 3..nes_color * 3 + 3]);
+            }
+        }
+    }
+}
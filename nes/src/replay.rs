@@ -1,4 +1,7 @@
-use crate::{controller::Button, Nes};
+use crate::{
+    controller::{Button, ControllerButtons},
+    Nes,
+};
 
 use serde::{Deserialize, Serialize};
 
@@ -59,4 +62,23 @@ impl Nes {
             self.run_one_frame();
         }
     }
+
+    pub(crate) fn queue_script_inputs(&mut self, script: Vec<(u32, ControllerButtons)>) {
+        self.input_script.extend(script);
+        self.input_script
+            .make_contiguous()
+            .sort_by_key(|&(frame, _)| frame);
+    }
+
+    /// Sets the buttons of the last script entry that is due before the next frame
+    pub(crate) fn apply_script_inputs(&mut self) {
+        while let Some(&(frame, buttons)) = self.input_script.front() {
+            if u64::from(frame) > self.frame_count {
+                break;
+            }
+
+            self.controller.set_buttons(buttons);
+            self.input_script.pop_front();
+        }
+    }
 }
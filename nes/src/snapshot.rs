@@ -0,0 +1,87 @@
+use std::hash::Hasher;
+
+use serde::{Deserialize, Serialize};
+use siphasher::sip::SipHasher13;
+
+use super::Nes;
+
+/// Human-readable emulator state for regression tests, see [`Nes::debug_snapshot`].
+/// Unlike save states, this can't be loaded back.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DebugSnapshot {
+    pub cpu: CpuSnapshot,
+    pub ppu: PpuSnapshot,
+    pub apu: ApuSnapshot,
+    pub mapper: MapperBanks,
+    pub frame_count: u64,
+    /// SipHash 1-3 of the frame buffer
+    pub frame_hash: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CpuSnapshot {
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub sp: u8,
+    /// Status flags in the NV-BDIZC order, B is always clear
+    pub p: u8,
+    /// Address of the next instruction
+    pub pc: u16,
+    pub cycle_count: u64,
+    pub irq_line: bool,
+    pub nmi_line: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PpuSnapshot {
+    pub scanline: u16,
+    pub xpos: u16,
+    pub odd_frame: bool,
+    /// The upper 3 bits of PPUSTATUS (vblank, sprite 0 hit, sprite overflow)
+    pub status: u8,
+    pub vram_addr: u16,
+    pub temp_vram_addr: u16,
+    pub fine_x: u8,
+    pub write_toggle: bool,
+    pub rendering_enabled: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ApuSnapshot {
+    pub pulse_1_length: u8,
+    pub pulse_1_volume: u8,
+    pub pulse_2_length: u8,
+    pub pulse_2_volume: u8,
+    pub triangle_length: u8,
+    pub triangle_linear_counter: u8,
+    pub noise_length: u8,
+    pub noise_volume: u8,
+    pub dmc_bytes_remaining: u16,
+    pub dmc_output_level: u8,
+    pub frame_irq: bool,
+}
+
+/// Offsets into PRG ROM and CHR memory of the currently mapped banks, ordered by CPU / PPU
+/// address. The window sizes depend on the mapper and its banking mode.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MapperBanks {
+    pub prg: Vec<usize>,
+    pub chr: Vec<usize>,
+}
+
+impl Nes {
+    pub(crate) fn snapshot(&self) -> DebugSnapshot {
+        let mut hasher = SipHasher13::new();
+        hasher.write(&self.ppu.output_buffer);
+
+        DebugSnapshot {
+            cpu: self.cpu_snapshot(),
+            ppu: self.ppu_snapshot(),
+            apu: self.apu_snapshot(),
+            mapper: self.mapper.banks(),
+            frame_count: self.frame_count,
+            frame_hash: hasher.finish(),
+        }
+    }
+}
@@ -0,0 +1,682 @@
+use std::sync::{Arc, Mutex};
+
+use super::*;
+use crate::{AudioChannel, NesBuilder, Region};
+
+/// Pulse 1: 75% duty, constant volume 15, longest period
+fn play_pulse_1(nes: &mut Nes) {
+    nes.cpu_write(0x4015, 0x01);
+    nes.cpu_write(0x4000, 0xDF);
+    nes.cpu_write(0x4002, 0xFF);
+    nes.cpu_write(0x4003, 0x07);
+}
+
+/// Pulse 1: 75% duty, constant volume 15, longest period that the sweep unit doesn't mute ($3FF)
+fn play_audible_pulse_1(nes: &mut Nes) {
+    nes.cpu_write(0x4015, 0x01);
+    nes.cpu_write(0x4000, 0xDF);
+    nes.cpu_write(0x4002, 0xFF);
+    nes.cpu_write(0x4003, 0x03);
+}
+
+#[test]
+fn multitrack_recording_separates_channels() {
+    let mut nes = test_nes(&[]);
+    play_audible_pulse_1(&mut nes);
+
+    nes.start_multitrack_recording();
+    nes.run_one_frame();
+    let tracks = nes.stop_multitrack_recording();
+
+    assert!(!tracks[0].is_empty());
+    assert!(tracks[0].iter().any(|&s| s > 0.0));
+    for track in &tracks[1..] {
+        assert_eq!(track.len(), tracks[0].len());
+        assert!(track.iter().all(|&s| s == 0.0));
+    }
+
+    // Not recording anymore
+    nes.run_one_frame();
+    assert!(nes.stop_multitrack_recording().iter().all(|t| t.is_empty()));
+}
+
+#[test]
+fn master_volume_scales_output() {
+    let mut full = test_nes(&[]);
+    let mut half = test_nes(&[]);
+    half.set_master_volume(0.5);
+
+    for nes in [&mut full, &mut half] {
+        play_audible_pulse_1(nes);
+        nes.run_one_frame();
+    }
+
+    let (full, half) = (full.get_audio_buffer(), half.get_audio_buffer());
+    assert_eq!(full.len(), half.len());
+    assert!(full.iter().any(|&s| s > 0.0));
+    for (f, h) in full.iter().zip(half) {
+        assert!((f * 0.5 - h).abs() < 1e-6);
+    }
+}
+
+#[test]
+fn master_volume_clamps_output() {
+    let mut nes = test_nes(&[]);
+    nes.set_master_volume(1000.0);
+    play_audible_pulse_1(&mut nes);
+    nes.run_one_frame();
+
+    let samples = nes.get_audio_buffer();
+    assert!(samples.contains(&1.0));
+    assert!(samples.iter().all(|&s| (-1.0..=1.0).contains(&s)));
+}
+
+#[test]
+fn audio_fingerprint_of_a_fading_tone() {
+    let mut nes = test_nes(&[]);
+    // 440 Hz
+    nes.cpu_write(0x4015, 0x01);
+    nes.cpu_write(0x4002, 0xFD);
+    nes.cpu_write(0x4003, 0x08);
+
+    // The constant volume goes down by 1 every 4 frames
+    let mut samples = Vec::new();
+    for frame in 0..64 {
+        nes.cpu_write(0x4000, 0xB0 | (15 - frame / 4));
+        nes.run_one_frame();
+        samples.extend_from_slice(nes.get_audio_buffer());
+        nes.clear_audio_buffer();
+    }
+
+    // One nibble per 4 frames, starting from the lowest one
+    const GOLDEN: u64 = 0x0011_1222_3334_4445;
+    let fingerprint = Nes::audio_fingerprint(&samples);
+    assert!(
+        Nes::audio_fingerprints_match(fingerprint, GOLDEN),
+        "{:016X}",
+        fingerprint
+    );
+
+    // Small differences don't matter, a different envelope does
+    let noisy: Vec<f32> = samples
+        .iter()
+        .enumerate()
+        .map(|(i, s)| s + (i % 3) as f32 * 1e-4)
+        .collect();
+    assert!(Nes::audio_fingerprints_match(
+        Nes::audio_fingerprint(&noisy),
+        GOLDEN
+    ));
+    samples.reverse();
+    assert!(!Nes::audio_fingerprints_match(
+        Nes::audio_fingerprint(&samples),
+        GOLDEN
+    ));
+    assert_eq!(Nes::audio_fingerprint(&[0.5; 15]), 0);
+}
+
+#[test]
+fn stereo_pans_channels() {
+    let mut mono = test_nes(&[]);
+    let mut stereo = test_nes(&[]);
+    stereo.set_stereo(true);
+    stereo.set_channel_pan(AudioChannel::Pulse1, -1.0);
+
+    for nes in [&mut mono, &mut stereo] {
+        play_audible_pulse_1(nes);
+        nes.run_one_frame();
+    }
+
+    let (mono, stereo) = (mono.get_audio_buffer(), stereo.get_audio_buffer());
+    assert_eq!(stereo.len(), 2 * mono.len());
+    assert!(mono.iter().any(|&s| s > 0.0));
+    for (sample, frame) in mono.iter().zip(stereo.chunks(2)) {
+        assert!((frame[0] - sample).abs() < 1e-6);
+        assert!(frame[1].abs() < 1e-6);
+    }
+}
+
+/// Runs an OAM DMA while the DMC plays a sample of the given length register value.
+/// Returns the cycles spent from the STA $4014 up to the next instruction,
+/// and the number of sample bytes fetched in the meantime.
+fn oam_dma_with_dmc(sample_length: u8) -> (u64, u16) {
+    let mut nes = test_nes(&[(
+        0x8000,
+        &[
+            0xA2, 0x6E, // LDX #110
+            0xCA, // DEX
+            0xD0, 0xFD, // BNE $8002
+            0xA9, 0x02, // LDA #$02
+            0x8D, 0x14, 0x40, // STA $4014
+            0xEA, // NOP
+            0x4C, 0x0A, 0x80, // JMP $800A
+        ],
+    )]);
+
+    // Fastest rate, the first fetch happens right away, the next one during the OAM DMA
+    nes.cpu_write(0x4010, 0x0F);
+    nes.cpu_write(0x4013, sample_length);
+    nes.cpu_write(0x4015, 0x10);
+
+    while nes.cpu.current_instruction != 0x8D {
+        nes.cpu_tick();
+    }
+    let (start_cycles, start_bytes) = (nes.get_cycle_count(), nes.apu.dmc.bytes_remaining);
+
+    while nes.cpu.current_instruction != 0xEA {
+        nes.cpu_tick();
+    }
+
+    (
+        nes.get_cycle_count() - start_cycles,
+        start_bytes - nes.apu.dmc.bytes_remaining,
+    )
+}
+
+#[test]
+fn dmc_dma_during_oam_dma() {
+    // A 1-byte sample is fetched before the OAM DMA starts
+    let (oam_only, fetched) = oam_dma_with_dmc(0x00);
+    assert_eq!(fetched, 0);
+
+    // The DMC fetch steals a get cycle from the OAM DMA, which then needs to realign
+    let (both, fetched) = oam_dma_with_dmc(0x01);
+    assert_eq!(fetched, 1);
+    assert_eq!(both, oam_only + 2);
+}
+
+#[test]
+fn dmc_dma_halts_cpu() {
+    // LDA $00 in a loop
+    let mut nes = test_nes(&[(0x8000, &[0xA5, 0x00, 0x4C, 0x00, 0x80])]);
+    nes.cpu_tick();
+
+    let start = nes.get_cycle_count();
+    nes.cpu_write(0x4015, 0x10);
+    nes.cpu_tick();
+
+    // The 3-cycle LDA gets stalled by 3 or 4 cycles depending on alignment
+    let elapsed = nes.get_cycle_count() - start;
+    assert!(elapsed == 6 || elapsed == 7, "took {} cycles", elapsed);
+    assert_eq!(nes.apu.dmc.bytes_remaining, 0);
+}
+
+#[test]
+fn direct_reads_dont_run_the_dmc_dma() {
+    let mut nes = test_nes(&[]);
+    nes.cpu_tick();
+    nes.cpu_write(0x4015, 0x10);
+
+    let start = nes.get_cycle_count();
+    assert_eq!(nes.cpu_read(0x4015) & 0x10, 0x10);
+    assert_eq!(nes.get_cycle_count(), start);
+    assert_eq!(nes.apu.dmc.bytes_remaining, 1);
+
+    // The next CPU read cycle is halted instead
+    nes.cpu_tick();
+    assert_eq!(nes.apu.dmc.bytes_remaining, 0);
+}
+
+#[test]
+fn dma_stall_cycles_are_counted_per_frame() {
+    // LDA #$02, STA $4014, JMP $8005
+    let mut nes = test_nes(&[(0x8000, &[0xA9, 0x02, 0x8D, 0x14, 0x40, 0x4C, 0x05, 0x80])]);
+    nes.run_one_frame();
+    let stalled = nes.dma_stall_cycles_this_frame();
+    assert!(
+        stalled == 513 || stalled == 514,
+        "stalled for {} cycles",
+        stalled
+    );
+
+    nes.run_one_frame();
+    assert_eq!(nes.dma_stall_cycles_this_frame(), 0);
+
+    // A 1-byte DMC sample
+    nes.cpu_write(0x4015, 0x10);
+    nes.run_one_frame();
+    let stalled = nes.dma_stall_cycles_this_frame();
+    assert!(
+        stalled == 3 || stalled == 4,
+        "stalled for {} cycles",
+        stalled
+    );
+}
+
+/// Triangle: longest linear counter reload, the length counter is loaded with 254
+fn play_triangle(nes: &mut Nes, period: u16) {
+    nes.cpu_write(0x4015, 0x04);
+    nes.cpu_write(0x4008, 0x7F);
+    nes.cpu_write(0x400A, period as u8);
+    nes.cpu_write(0x400B, 0x08 | (period >> 8) as u8);
+}
+
+#[test]
+fn triangle_ultrasonic_period_freezes_sequencer() {
+    let mut nes = test_nes(&[]);
+    play_triangle(&mut nes, 1);
+
+    let start = nes.apu.triangle.sequence_pos;
+    // Past the first quarter frame, which loads the linear counter
+    while nes.get_cycle_count() < 20000 {
+        nes.cpu_tick();
+    }
+    assert_eq!(nes.apu.triangle.sequence_pos, start);
+
+    play_triangle(&mut nes, 0x40);
+    while nes.get_cycle_count() < 40000 {
+        nes.cpu_tick();
+    }
+    assert_ne!(nes.apu.triangle.sequence_pos, start);
+}
+
+#[test]
+fn triangle_keeps_output_level_when_silenced() {
+    let mut nes = test_nes(&[]);
+    play_triangle(&mut nes, 0x40);
+    while nes.get_cycle_count() < 10001 {
+        nes.cpu_tick();
+    }
+
+    nes.cpu_write(0x4015, 0);
+    let pos = nes.apu.triangle.sequence_pos;
+    nes.start_multitrack_recording();
+    nes.run_one_frame();
+    let tracks = nes.stop_multitrack_recording();
+
+    assert_eq!(nes.apu.triangle.sequence_pos, pos);
+    assert!(tracks[2].iter().all(|&s| s == tracks[2][0]));
+}
+
+/// Plays a 1 byte DMC sample with the given $4010 value until it has been fetched
+fn play_short_dmc_sample(nes: &mut Nes, flags: u8) {
+    // The reset sequence silences the APU
+    if nes.get_cycle_count() == 0 {
+        nes.cpu_tick();
+    }
+
+    // No frame IRQs
+    nes.cpu_write(0x4017, 0x40);
+    nes.cpu_write(0x4010, flags);
+    nes.cpu_write(0x4013, 0);
+    nes.cpu_write(0x4015, 0x10);
+    // The previous sample byte may still be playing
+    for _ in 0..1000 {
+        nes.cpu_tick();
+    }
+}
+
+#[test]
+fn dmc_irq_flag() {
+    let mut nes = test_nes(&[]);
+    play_short_dmc_sample(&mut nes, 0x8F);
+    assert_eq!(nes.apu.dmc.bytes_remaining, 0);
+    assert!(nes.apu_irq_pending().dmc);
+
+    let status = nes.cpu_read(0x4015);
+    assert_ne!(status & 0x80, 0);
+    assert_eq!(status & 0x10, 0);
+    assert_eq!(nes.cpu_read(0x4015) & 0x80, 0);
+
+    // Cleared by $4010 writes
+    play_short_dmc_sample(&mut nes, 0x8F);
+    assert!(nes.apu_irq_pending().dmc);
+    nes.cpu_write(0x4010, 0x8F);
+    assert!(!nes.apu_irq_pending().dmc);
+
+    // Not set without the IRQ enabled, or by looping samples
+    for flags in [0x0F, 0xCF] {
+        let mut nes = test_nes(&[]);
+        play_short_dmc_sample(&mut nes, flags);
+        assert_eq!(nes.cpu_read(0x4015) & 0x80, 0);
+    }
+}
+
+#[test]
+fn apu_irq_pending_doesnt_acknowledge() {
+    let mut nes = test_nes(&[]);
+    // The frame counter starts in the 4-step mode with IRQs enabled
+    while nes.get_cycle_count() < 29840 {
+        nes.cpu_tick();
+    }
+
+    assert!(nes.apu_irq_pending().frame);
+    assert!(nes.apu_irq_pending().frame);
+    assert!(!nes.apu_irq_pending().dmc);
+
+    assert_ne!(nes.cpu_read(0x4015) & 0x40, 0);
+    assert!(!nes.apu_irq_pending().frame);
+    assert_eq!(nes.cpu_read(0x4015) & 0x40, 0);
+}
+
+#[test]
+fn frame_irq_stays_asserted_until_acknowledged() {
+    let mut nes = test_nes(&[]);
+    nes.cpu_tick();
+    while !nes.apu_irq_pending().frame {
+        nes.clock_ppu_apu();
+    }
+
+    // Set for the whole 3 cycle window and afterwards, acknowledging inside the window is undone
+    for cycle in 0..10 {
+        assert!(nes.apu_irq_pending().frame, "{}", cycle);
+        assert!(nes.debug_snapshot().cpu.irq_line, "{}", cycle);
+        if cycle == 1 {
+            assert_ne!(nes.cpu_read(0x4015) & 0x40, 0);
+        }
+        nes.clock_ppu_apu();
+    }
+
+    // Writing $4017 without the inhibit flag leaves it alone
+    nes.cpu_write(0x4017, 0);
+    assert!(nes.apu_irq_pending().frame);
+    nes.cpu_write(0x4017, 0x40);
+    assert!(!nes.apu_irq_pending().frame);
+    assert!(!nes.debug_snapshot().cpu.irq_line);
+}
+
+#[test]
+fn frame_irq_inhibit() {
+    let mut nes = test_nes(&[]);
+    nes.cpu_write(0x4017, 0x40);
+    while nes.get_cycle_count() < 29840 {
+        nes.cpu_tick();
+    }
+
+    assert!(!nes.apu_irq_pending().frame);
+}
+
+#[test]
+fn unused_registers_dont_trigger_unimplemented_write_hook() {
+    let mut nes = test_nes(&[]);
+
+    let writes = Arc::new(Mutex::new(Vec::new()));
+    let hook_writes = Arc::clone(&writes);
+    nes.set_unimplemented_write_hook(Box::new(move |addr, val| {
+        hook_writes.lock().unwrap().push((addr, val))
+    }));
+
+    nes.cpu_write(0x4008, 0x7F);
+    nes.cpu_write(0x4009, 0x11);
+    nes.cpu_write(0x400D, 0x22);
+    assert!(writes.lock().unwrap().is_empty());
+
+    nes.cpu_write(0x4018, 0x33);
+    nes.cpu_write(0x401F, 0x44);
+    assert_eq!(*writes.lock().unwrap(), [(0x4018, 0x33), (0x401F, 0x44)]);
+}
+
+#[test]
+fn dmc_state_tracks_sample_progress() {
+    let mut nes = test_nes(&[]);
+    nes.cpu_tick();
+    assert!(!nes.dmc_state().playing);
+
+    // 257 bytes from $C000 at the fastest rate, a bit under 4 frames
+    nes.cpu_write(0x4010, 0x0F);
+    nes.cpu_write(0x4012, 0);
+    nes.cpu_write(0x4013, 0x10);
+    nes.cpu_write(0x4015, 0x10);
+    let start = nes.dmc_state();
+    assert!(start.playing);
+    assert_eq!(start.bytes_remaining, 257);
+    assert_eq!(start.current_address, 0xC000);
+
+    let mut remaining = start.bytes_remaining;
+    for _ in 0..3 {
+        nes.run_one_frame();
+        let state = nes.dmc_state();
+        assert!(state.playing);
+        assert!(state.bytes_remaining < remaining);
+        assert_eq!(state.current_address, 0xC000 + 257 - state.bytes_remaining);
+        remaining = state.bytes_remaining;
+    }
+
+    nes.run_one_frame();
+    let end = nes.dmc_state();
+    assert!(!end.playing);
+    assert_eq!(end.bytes_remaining, 0);
+}
+
+#[test]
+fn run_until_audio_samples_fills_the_buffer() {
+    let mut nes = test_nes(&[]);
+    // About 735 samples per frame at 44.1 kHz
+    assert_eq!(nes.run_until_audio_samples(4096), 6);
+    assert!(nes.get_audio_buffer().len() >= 4096);
+    assert_eq!(nes.run_until_audio_samples(4096), 0);
+
+    nes.clear_audio_buffer();
+    nes.set_stereo(true);
+    nes.run_until_audio_samples(4096);
+    assert!(nes.get_audio_buffer().len() >= 2 * 4096);
+}
+
+/// Reads $4015 `offset` cycles after the frame IRQ flag gets set, returns the frame IRQ bit of
+/// the read and whether the flag is still set at the end of the read cycle
+fn read_status_near_frame_irq(offset: i64) -> (bool, bool) {
+    let mut nes = test_nes(&[]);
+    nes.cpu_tick();
+    nes.cpu_write(0x4017, 0);
+
+    let mut cycles = 0;
+    while !nes.apu_irq_pending().frame {
+        nes.clock_ppu_apu();
+        cycles += 1;
+    }
+
+    let mut nes = test_nes(&[]);
+    nes.cpu_tick();
+    nes.cpu_write(0x4017, 0);
+    for _ in 0..cycles + offset {
+        nes.clock_ppu_apu();
+    }
+
+    let status = nes.cpu_read(0x4015);
+    nes.clock_ppu_apu();
+    (status & 0x40 != 0, nes.apu_irq_pending().frame)
+}
+
+#[test]
+fn status_read_races_the_frame_irq() {
+    assert_eq!(read_status_near_frame_irq(-2), (false, false));
+    // The flag is set on the same cycle as the read, and on the next 2 cycles
+    assert_eq!(read_status_near_frame_irq(-1), (true, true));
+    assert_eq!(read_status_near_frame_irq(0), (true, true));
+    assert_eq!(read_status_near_frame_irq(1), (true, true));
+    assert_eq!(read_status_near_frame_irq(2), (true, false));
+}
+
+/// Pulse 1 length counter after `write`, made `offset` cycles after the first length counter
+/// clock. The counter is loaded with 254 beforehand if `loaded`.
+fn write_near_length_clock(loaded: bool, offset: i64, write: (usize, u8)) -> u8 {
+    let setup = |nes: &mut Nes, loaded: bool| {
+        nes.cpu_tick();
+        nes.cpu_write(0x4015, 0x01);
+        if loaded {
+            nes.cpu_write(0x4003, 0x08);
+        }
+        nes.clock_ppu_apu();
+    };
+
+    let mut nes = test_nes(&[]);
+    setup(&mut nes, true);
+    let mut cycles = 0;
+    while nes.debug_snapshot().apu.pulse_1_length == 254 {
+        nes.clock_ppu_apu();
+        cycles += 1;
+    }
+
+    let mut nes = test_nes(&[]);
+    setup(&mut nes, loaded);
+    for _ in 1..cycles + offset {
+        nes.clock_ppu_apu();
+    }
+    nes.cpu_write(write.0, write.1);
+    for _ in cycles + offset..=cycles + 1 {
+        nes.clock_ppu_apu();
+    }
+    nes.debug_snapshot().apu.pulse_1_length
+}
+
+#[test]
+fn length_halt_takes_effect_after_the_clock() {
+    assert_eq!(write_near_length_clock(true, -1, (0x4000, 0x20)), 254);
+    assert_eq!(write_near_length_clock(true, 0, (0x4000, 0x20)), 253);
+    // Clearing the halt flag
+    assert_eq!(write_near_length_clock(true, -1, (0x4000, 0x00)), 253);
+}
+
+#[test]
+fn length_reload_during_the_clock_is_ignored() {
+    assert_eq!(write_near_length_clock(true, -1, (0x4003, 0x00)), 9);
+    assert_eq!(write_near_length_clock(true, 0, (0x4003, 0x00)), 253);
+    assert_eq!(write_near_length_clock(true, 1, (0x4003, 0x00)), 10);
+    // Unless the counter is 0
+    assert_eq!(write_near_length_clock(false, 0, (0x4003, 0x00)), 10);
+}
+
+/// Pulse 1 track of one frame with 50% duty, constant volume 15 and the timer period `period`,
+/// with a sample every CPU cycle
+fn pulse_1_track(period: u16) -> Vec<f32> {
+    let mut nes = NesBuilder::new()
+        .sample_rate(Region::Ntsc.cpu_clock_rate().ceil() as u32)
+        .build_from_bytes(&test_rom(&[]))
+        .expect("error when creating test NES instance");
+    nes.cpu_write(0x4015, 0x01);
+    nes.cpu_write(0x4000, 0x9F);
+    nes.cpu_write(0x4002, period as u8);
+    nes.cpu_write(0x4003, (period >> 8) as u8);
+
+    nes.start_multitrack_recording();
+    nes.run_one_frame();
+    nes.stop_multitrack_recording()[0].clone()
+}
+
+#[test]
+fn pulse_minimum_period_mutes() {
+    assert!(pulse_1_track(7).iter().all(|&s| s == 0.0));
+
+    // The output stays up for the 4 high duty steps, instead of dropping out whenever the timer
+    // counts down below 8
+    for period in [8, 0x100] {
+        let track = pulse_1_track(period);
+        let high = track.iter().copied().fold(0.0, f32::max);
+        assert!(high > 0.0, "{}", period);
+        assert!(track.iter().all(|&s| s == 0.0 || s == high), "{}", period);
+
+        let runs: Vec<usize> = track
+            .split(|&s| s == 0.0)
+            .map(<[f32]>::len)
+            .filter(|&len| len > 0)
+            .collect();
+        // The first and the last run might be cut off by the frame
+        let step_cycles = 2 * (usize::from(period) + 1);
+        for &len in &runs[1..runs.len() - 1] {
+            assert_eq!(len, 4 * step_cycles, "{}", period);
+        }
+    }
+}
+
+#[test]
+fn apu_register_shadow_keeps_written_values() {
+    let mut nes = test_nes(&[]);
+    nes.cpu_write(0x4000, 0xBF);
+    nes.cpu_write(0x4003, 0x12);
+    nes.cpu_write(0x400E, 0x84);
+    nes.cpu_write(0x4015, 0x0F);
+    nes.cpu_write(0x4017, 0x40);
+    // Not an APU register
+    nes.cpu_write(0x4016, 0x01);
+
+    let shadow = nes.apu_register_shadow();
+    assert_eq!(shadow[0x00], 0xBF);
+    assert_eq!(shadow[0x03], 0x12);
+    assert_eq!(shadow[0x0E], 0x84);
+    assert_eq!(shadow[0x15], 0x0F);
+    assert_eq!(shadow[0x17], 0x40);
+    assert_eq!(shadow[0x16], 0);
+
+    let mut loaded = test_nes(&[]);
+    loaded.load_state(&nes.save_state().unwrap()).unwrap();
+    assert_eq!(loaded.apu_register_shadow(), shadow);
+}
+
+/// Pulse 1 and pulse 2 tracks of the `frame`th frame, with both channels set up the same way
+fn sweep_tracks(period: u16, sweep: u8, frame: u64) -> (Vec<f32>, Vec<f32>) {
+    let mut nes = test_nes(&[]);
+    nes.cpu_tick();
+    nes.cpu_write(0x4015, 0x03);
+    for base in [0x4000, 0x4004] {
+        nes.cpu_write(base, 0xBF);
+        nes.cpu_write(base + 1, sweep);
+        nes.cpu_write(base + 2, period as u8);
+        nes.cpu_write(base + 3, (period >> 8) as u8);
+    }
+
+    while nes.get_frame_count() < frame {
+        nes.run_one_frame();
+    }
+    nes.start_multitrack_recording();
+    nes.run_one_frame();
+    let tracks = nes.stop_multitrack_recording();
+    (tracks[0].clone(), tracks[1].clone())
+}
+
+#[test]
+fn sweep_overflow_mutes_without_panicking() {
+    // Adds half of the period every half frame, the target period is already above $7FF
+    let (pulse_1, pulse_2) = sweep_tracks(0x7FF, 0x81, 0);
+    assert!(pulse_1.iter().all(|&s| s == 0.0));
+    assert!(pulse_2.iter().all(|&s| s == 0.0));
+
+    let (pulse_1, pulse_2) = sweep_tracks(0x7FF, 0x81, 20);
+    assert!(pulse_1.iter().all(|&s| s == 0.0));
+    assert!(pulse_2.iter().all(|&s| s == 0.0));
+}
+
+#[test]
+fn sweep_negate_differs_between_pulses() {
+    // One update of $F - ($F >> 1) after 9 half frames: 7 on pulse 1 (muted), 8 on pulse 2
+    let (pulse_1, pulse_2) = sweep_tracks(0xF, 0xF9, 6);
+    assert!(pulse_1.iter().all(|&s| s == 0.0));
+    assert!(pulse_2.iter().any(|&s| s > 0.0));
+}
+
+#[test]
+fn sweep_target_mutes_without_changing_the_period() {
+    // Enabled without a shift count, the target period is twice the period
+    for (period, audible) in [(0x3FF, true), (0x400, false)] {
+        let mut nes = test_nes(&[]);
+        nes.cpu_tick();
+        nes.cpu_write(0x4015, 0x01);
+        nes.cpu_write(0x4000, 0xBF);
+        nes.cpu_write(0x4001, 0x80);
+        nes.cpu_write(0x4002, period as u8);
+        nes.cpu_write(0x4003, (period >> 8) as u8);
+
+        // The channel would be muted by now if the target period had been applied
+        for _ in 0..4 {
+            nes.run_one_frame();
+        }
+        nes.start_multitrack_recording();
+        nes.run_one_frame();
+        let pulse_1 = &nes.stop_multitrack_recording()[0];
+        assert_eq!(pulse_1.iter().any(|&s| s > 0.0), audible, "{:X}", period);
+    }
+}
+
+#[test]
+fn disabled_sweep_mutes_by_the_target_period() {
+    // With a shift count of 0, the target period is twice the period
+    let mut longest = test_nes(&[]);
+    let mut audible = test_nes(&[]);
+    play_pulse_1(&mut longest);
+    play_audible_pulse_1(&mut audible);
+
+    for nes in [&mut longest, &mut audible] {
+        nes.run_one_frame();
+    }
+    assert!(longest.get_audio_buffer().iter().all(|&s| s == 0.0));
+    assert!(audible.get_audio_buffer().iter().any(|&s| s > 0.0));
+}
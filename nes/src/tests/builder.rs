@@ -0,0 +1,76 @@
+use super::*;
+use crate::{NesBuilder, RamInit, Region};
+
+#[test]
+fn builder_applies_region_and_ram_init() {
+    let mut rom = test_rom(&[]);
+    // iNES PAL flag
+    rom[9] = 0x01;
+    assert!(matches!(
+        Nes::new(&rom),
+        Err(NesError::RegionUnsupported(Region::Pal))
+    ));
+
+    let mut nes = NesBuilder::new()
+        .region(Region::Ntsc)
+        .ram_init(RamInit::Fill(0xA5))
+        .build_from_bytes(&rom)
+        .expect("error when creating test NES instance");
+
+    assert_eq!(nes.region(), Region::Ntsc);
+    assert_eq!(nes.cpu_read(0x0300), 0xA5);
+    assert_eq!(nes.cpu_read(0x07FF), 0xA5);
+
+    let dendy = NesBuilder::new()
+        .region(Region::Dendy)
+        .build_from_bytes(&test_rom(&[]));
+    assert!(matches!(
+        dendy,
+        Err(NesError::RegionUnsupported(Region::Dendy))
+    ));
+}
+
+#[test]
+fn builder_defaults_match_nes_new() {
+    let mut nes = Nes::new(&test_rom(&[])).expect("error when creating test NES instance");
+
+    assert_eq!(nes.region(), Region::Ntsc);
+    assert_eq!(nes.cpu_read(0x0300), 0);
+
+    nes.run_one_frame();
+    let start = nes.get_cycle_count();
+    nes.run_one_frame();
+    let frame_cycles = nes.get_cycle_count() - start;
+    assert!((29779..=29782).contains(&frame_cycles), "{}", frame_cycles);
+}
+
+#[test]
+fn reset_keeps_ram_and_power_cycle_clears_it() {
+    let mut nes = NesBuilder::new()
+        .ram_init(RamInit::Fill(0xA5))
+        .build_from_bytes(&test_rom(&[]))
+        .expect("error when creating test NES instance");
+    nes.run_until(10, |nes| nes.ppu_warmed_up());
+
+    nes.write_range(0x0300, b"WARM");
+    nes.cpu_write(0x2001, 0x1E);
+    nes.reset();
+
+    // The PPU ignores PPUMASK writes until the end of the first vblank after the reset
+    assert!(!nes.debug_snapshot().ppu.rendering_enabled);
+    assert!(!nes.ppu_warmed_up());
+    nes.cpu_write(0x2001, 0x1E);
+    assert!(!nes.debug_snapshot().ppu.rendering_enabled);
+    nes.run_one_frame();
+    assert!(nes.ppu_warmed_up());
+
+    let mut signature = [0; 4];
+    nes.read_range(0x0300, 4, &mut signature);
+    assert_eq!(&signature, b"WARM");
+
+    nes.power_cycle();
+    nes.read_range(0x0300, 4, &mut signature);
+    assert_eq!(signature, [0xA5; 4]);
+    assert_eq!(nes.get_cycle_count(), 0);
+    assert_eq!(nes.get_frame_count(), 0);
+}
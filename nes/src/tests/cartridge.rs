@@ -0,0 +1,131 @@
+use super::*;
+use crate::{cartridge::Header, Region};
+
+#[test]
+fn scan_directory_lists_roms() {
+    let entries = Nes::scan_directory(&test_path("cpu/instr_misc/rom_singles"));
+
+    assert_eq!(entries.len(), 4);
+    for entry in &entries {
+        let header = entry
+            .header
+            .as_ref()
+            .expect("error when parsing the header");
+        assert_eq!(header.mapper, 0);
+        assert_eq!(header.prg_rom_size, 0x8000);
+        assert_eq!(header.chr_rom_size, Some(0x2000));
+        assert!(!header.battery);
+    }
+}
+
+#[test]
+fn scan_directory_skips_non_roms() {
+    // Contains readme.txt and the rom_singles directory besides the ROM itself
+    let entries = Nes::scan_directory(&test_path("cpu/cpu_interrupts_v2"));
+
+    assert_eq!(entries.len(), 1);
+    assert!(entries[0].path.ends_with("cpu_interrupts.nes"));
+}
+
+#[test]
+fn scan_directory_reports_errors_per_entry() {
+    let dir = env::temp_dir().join("fearless_nes_scan_directory");
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("a_truncated.nes"), [0x4E, 0x45, 0x53]).unwrap();
+    fs::write(dir.join("b_valid.nes"), test_rom(&[])).unwrap();
+
+    let entries = Nes::scan_directory(&dir);
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(entries.len(), 2);
+    assert!(matches!(
+        entries[0].header,
+        Err(NesError::InvalidInesFormat)
+    ));
+    assert!(entries[1].header.is_ok());
+}
+
+#[test]
+fn load_cartridge_from_bytes_power_cycles() {
+    // Stores $55 to $10
+    let mut nes = test_nes(&[(0x8000, &[0xA9, 0x55, 0x85, 0x10, 0x4C, 0x04, 0x80])]);
+    nes.set_master_volume(0.5);
+    nes.run_one_frame();
+    assert_eq!(nes.peek(0x10), 0x55);
+
+    // Starts at $9000 and stores $77 to $11
+    let rom_b = test_rom(&[
+        (0x9000, &[0xA9, 0x77, 0x85, 0x11, 0x4C, 0x04, 0x90]),
+        (0xFFFC, &[0x00, 0x90]),
+    ]);
+    nes.load_cartridge_from_bytes(&rom_b).unwrap();
+
+    assert_eq!(nes.get_frame_count(), 0);
+    assert_eq!(nes.get_cycle_count(), 0);
+    assert_eq!(nes.peek(0x10), 0);
+    assert_eq!(nes.get_master_volume(), 0.5);
+
+    nes.add_breakpoint(0x9000);
+    assert_eq!(nes.run_instructions(10), StopReason::Breakpoint(0x9000));
+    nes.run_instructions(2);
+    assert_eq!(nes.peek(0x11), 0x77);
+
+    // A bad ROM leaves the current game alone
+    assert!(nes.load_cartridge_from_bytes(&[0x4E, 0x45]).is_err());
+    assert_eq!(nes.peek(0x11), 0x77);
+}
+
+#[test]
+fn nes_2_header_reads_the_region() {
+    let mut rom = test_rom(&[]);
+    // NES 2.0 identifier, submapper 1, 8KB of PRG RAM and PAL timing
+    rom[7] = 0x08;
+    rom[8] = 0x10;
+    rom[10] = 0x07;
+    rom[12] = 0x01;
+
+    let header = Header::from_rom(&rom).unwrap();
+    assert_eq!(header.prg_rom_size, 0x4000);
+    assert_eq!(header.chr_ram_size, Some(0x2000));
+    assert_eq!(header.prg_ram_size, Some(0x2000));
+    assert_eq!(header.submapper, 1);
+    assert_eq!(header.region, Region::Pal);
+
+    // Only NTSC timing is emulated, PAL games are rejected
+    assert!(matches!(
+        Nes::new(&rom),
+        Err(NesError::RegionUnsupported(Region::Pal))
+    ));
+
+    // "Multi-region" games run as NTSC
+    rom[12] = 0x02;
+    assert_eq!(Nes::new(&rom).unwrap().region(), Region::Ntsc);
+}
+
+#[test]
+fn dump_prg_and_chr() {
+    let rom = fs::read(test_path("cpu/nestest/nestest.nes")).unwrap();
+    let header = Header::from_rom(&rom).unwrap();
+    let nes = Nes::new(&rom).unwrap();
+
+    assert_eq!(nes.dump_prg().len(), header.prg_rom_size as usize);
+    assert_eq!(nes.dump_prg(), &rom[16..16 + 0x4000]);
+    assert_eq!(Some(nes.dump_chr().len() as u32), header.chr_rom_size);
+
+    let dir = env::temp_dir().join("fearless_nes_dump");
+    fs::create_dir_all(&dir).unwrap();
+    nes.dump_to_files(&dir.join("prg.bin"), &dir.join("chr.bin"))
+        .unwrap();
+    let (prg, chr) = (fs::read(dir.join("prg.bin")), fs::read(dir.join("chr.bin")));
+    fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(prg.unwrap(), nes.dump_prg());
+    assert_eq!(chr.unwrap(), nes.dump_chr());
+
+    // CHR RAM is dumped with its current contents
+    let mut nes = warmed_up_test_nes(&[]);
+    nes.cpu_write(0x2006, 0x01);
+    nes.cpu_write(0x2006, 0x23);
+    nes.cpu_write(0x2007, 0xAB);
+    assert_eq!(nes.dump_chr().len(), 0x2000);
+    assert_eq!(nes.dump_chr()[0x123], 0xAB);
+}
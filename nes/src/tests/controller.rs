@@ -0,0 +1,96 @@
+use super::*;
+use crate::{Button, ControllerButtons};
+
+#[test]
+fn controller_read_log_records_reads() {
+    let mut nes = test_nes(&[]);
+    nes.set_controller_read_log(16);
+    nes.run_one_frame();
+
+    nes.set_button_state(Button::A, true);
+    nes.set_button_state(Button::Start, true);
+    nes.cpu_write(0x4016, 1);
+    nes.cpu_write(0x4016, 0);
+    for _ in 0..8 {
+        nes.cpu_read(0x4016);
+    }
+
+    let log = nes.controller_read_log();
+    assert_eq!(log.len(), 8);
+    let buttons: Vec<u8> = log.iter().map(|read| read.value & 1).collect();
+    assert_eq!(buttons, [1, 0, 0, 1, 0, 0, 0, 0]);
+    for read in log {
+        assert_eq!(read.addr, 0x4016);
+        assert_eq!(read.frame, nes.get_frame_count());
+    }
+}
+
+#[test]
+fn controller_read_log_is_bounded() {
+    let mut nes = test_nes(&[]);
+    assert!(nes.controller_read_log().is_empty());
+
+    nes.cpu_read(0x4016);
+    assert!(nes.controller_read_log().is_empty());
+
+    nes.set_controller_read_log(4);
+    for _ in 0..6 {
+        nes.cpu_read(0x4016);
+    }
+    nes.cpu_read(0x4017);
+
+    let log = nes.controller_read_log();
+    assert_eq!(log.len(), 4);
+    assert_eq!(log.back().unwrap().addr, 0x4017);
+}
+
+#[test]
+fn input_script_sets_buttons_at_frames() {
+    let mut nes = test_nes(&[]);
+    let start = ControllerButtons::pressed(&[Button::Start]);
+    let right_a = ControllerButtons::pressed(&[Button::Right, Button::A]);
+    nes.queue_input_script(vec![
+        (5, right_a),
+        (2, start),
+        (4, ControllerButtons::default()),
+    ]);
+
+    let mut states = Vec::new();
+    for _ in 0..7 {
+        nes.run_one_frame();
+        states.push(nes.controller_buttons());
+    }
+
+    // The state after frames 0 - 6 have run
+    let none = ControllerButtons::default();
+    assert_eq!(states, [none, none, start, start, none, right_a, right_a]);
+    assert!(right_a.is_pressed(Button::A));
+    assert!(!right_a.is_pressed(Button::Left));
+    assert_eq!(right_a.0, 0x81);
+}
+
+#[test]
+fn strobe_high_keeps_reloading_a() {
+    let mut nes = test_nes(&[]);
+    for button in [Button::A, Button::Select, Button::Down] {
+        nes.set_button_state(button, true);
+    }
+
+    let read_buttons = |nes: &mut Nes, count: usize| -> Vec<u8> {
+        (0..count).map(|_| nes.cpu_read(0x4016) & 1).collect()
+    };
+
+    nes.cpu_write(0x4016, 1);
+    assert_eq!(read_buttons(&mut nes, 4), [1; 4]);
+
+    // The live state of A is read back while the strobe is high
+    nes.set_button_state(Button::A, false);
+    assert_eq!(read_buttons(&mut nes, 2), [0; 2]);
+    nes.set_button_state(Button::A, true);
+
+    // The state is latched when the strobe goes low, then 1s are read after the 8 buttons
+    nes.cpu_write(0x4016, 0);
+    nes.set_button_state(Button::Select, false);
+    assert_eq!(read_buttons(&mut nes, 8), [1, 0, 1, 0, 0, 1, 0, 0]);
+    assert_eq!(read_buttons(&mut nes, 4), [1; 4]);
+}
@@ -1,4 +1,10 @@
+use std::{
+    panic,
+    sync::{Arc, Mutex},
+};
+
 use super::*;
+use crate::{IrqSource, NesBuilder, RamInit};
 
 #[test]
 fn blargg_instr_all() {
@@ -123,3 +129,604 @@ fn branch_timing_tests_branch_forward() {
         10319321477101815957,
     );
 }
+
+#[test]
+fn cpu_interrupts_nmi_and_brk() {
+    blargg_test(
+        "cpu/cpu_interrupts_v2/rom_singles/2-nmi_and_brk.nes",
+        "NMI BRK 00\n27  36  00 \n26  36  00 \n26  36  00 \n36  00  00 \n36  00  00 \n36  00  00 \n36  00  00 \n36  00  00 \n27  36  00 \n27  36  00 \n\n2-nmi_and_brk\n\nPassed\n",
+    );
+}
+
+#[test]
+fn cpu_interrupts_cli_latency() {
+    blargg_test(
+        "cpu/cpu_interrupts_v2/rom_singles/1-cli_latency.nes",
+        "\n1-cli_latency\n\nPassed\n",
+    );
+}
+
+#[test]
+fn cpu_interrupts_nmi_and_irq() {
+    blargg_test(
+        "cpu/cpu_interrupts_v2/rom_singles/3-nmi_and_irq.nes",
+        "NMI BRK\n23  00 \n21  00 \n21  00 \n20  00 \n20  00 \n20  00 \n20  00 \n20  00 \n20  00 \n20  00 \n25  20 \n25  20 \n\n3-nmi_and_irq\n\nPassed\n",
+    );
+}
+
+/// Runs one instruction per `cpu_tick` and checks how many cycles each one took
+#[test]
+fn page_cross_and_branch_penalties() {
+    #[rustfmt::skip]
+    let program: &[u8] = &[
+        0xA2, 0x01,       // LDX #$01
+        0xA0, 0xFF,       // LDY #$FF
+        0xBD, 0x00, 0x02, // LDA $0200,X
+        0xBD, 0xFF, 0x02, // LDA $02FF,X (crosses)
+        0xB9, 0x00, 0x02, // LDA $0200,Y
+        0xB9, 0x01, 0x02, // LDA $0201,Y (crosses)
+        0x9D, 0x00, 0x02, // STA $0200,X
+        0x9D, 0xFF, 0x02, // STA $02FF,X (crosses)
+        0xFE, 0xFF, 0x02, // INC $02FF,X (crosses)
+        0xB5, 0xFF,       // LDA $FF,X (wraps within the zero page)
+        0xB1, 0x10,       // LDA ($10),Y ($0000 + $FF)
+        0xA9, 0x01,       // LDA #$01
+        0x85, 0x12,       // STA $12
+        0xB1, 0x12,       // LDA ($12),Y (crosses: $0001 + $FF)
+        0x91, 0x10,       // STA ($10),Y
+        0x18,             // CLC
+        0xB0, 0x00,       // BCS +0 (not taken)
+        0x90, 0x00,       // BCC +0 (taken)
+        0x4C, 0xFD, 0x80, // JMP $80FD
+    ];
+
+    let mut nes = test_nes(&[
+        (0x8000, program),
+        (0x80FD, &[0x90, 0x02]), // BCC $8101 (taken, crosses)
+        (0x8101, &[0x90, 0xF0]), // BCC $80F3 (taken, crosses backwards)
+    ]);
+
+    let expected = [
+        7, // reset sequence
+        2, 2, 4, 5, 4, 5, 5, 5, 7, 4, 5, 2, 3, 6, 6, 2, 2, 3, 3, // $8000..
+        4, 4, // page-crossing branches
+        2, // NOP at $80F3
+    ];
+
+    for (i, cycles) in expected.iter().enumerate() {
+        let start = nes.get_cycle_count();
+        nes.cpu_tick();
+        assert_eq!(
+            nes.get_cycle_count() - start,
+            *cycles,
+            "instruction {} (PC={:04X})",
+            i,
+            nes.cpu.pc
+        );
+    }
+
+    // The next opcode ($80F4) has already been fetched
+    assert_eq!(nes.cpu.pc, 0x80F5);
+}
+
+#[test]
+fn boot_hook_sets_pc() {
+    // LDA #$42; STA $10
+    let mut nes = test_nes(&[(0x8400, &[0xA9, 0x42, 0x85, 0x10])]);
+    nes.set_boot_hook(Box::new(|nes| {
+        nes.poke(0x0011, 0x99);
+        nes.set_pc(0x8400);
+    }));
+
+    nes.cpu_tick();
+    assert_eq!(nes.cpu.current_instruction, 0xA9);
+    assert_eq!(nes.cpu.pc, 0x8401);
+
+    nes.cpu_tick();
+    nes.cpu_tick();
+    assert_eq!(nes.peek(0x0010), 0x42);
+    assert_eq!(nes.peek(0x0011), 0x99);
+}
+
+#[test]
+fn late_boot_hook_waits_for_power_cycle() {
+    let mut nes = test_nes(&[]);
+    nes.run_instructions(2);
+    nes.set_boot_hook(Box::new(|nes| nes.poke(0x0011, 0x99)));
+
+    nes.cpu_tick();
+    assert_eq!(nes.peek(0x0011), 0);
+
+    nes.power_cycle();
+    nes.cpu_tick();
+    assert_eq!(nes.peek(0x0011), 0x99);
+}
+
+fn nestest() -> Nes {
+    let rom = fs::read(test_path("cpu/nestest/nestest.nes")).unwrap();
+    let mut nes = Nes::new(&rom).expect("error when creating test NES instance");
+    // Reset sequence, then start the automated mode like nestest.log does
+    nes.cpu_tick();
+    nes.set_pc(0xC000);
+    // The log starts with the stack pointer at $FD, at CYC:7 and PPU: 0, 21
+    nes.cpu.sp = 0xFD;
+    nes.set_cycle_origin(7);
+    nes.set_ppu_origin(0, 21).unwrap();
+    nes
+}
+
+#[test]
+fn run_instructions_nestest() {
+    let mut nes = nestest();
+
+    assert_eq!(nes.run_instructions(1), StopReason::Completed);
+    assert_eq!(nes.get_pc(), 0xC5F5);
+
+    // nestest.log, 10 lines after C000  JMP $C5F5
+    let mut nes = nestest();
+    assert_eq!(nes.run_instructions(10), StopReason::Completed);
+    assert_eq!(nes.get_pc(), 0xC736);
+}
+
+#[test]
+fn run_instructions_stops_at_breakpoints() {
+    let mut nes = nestest();
+    nes.add_breakpoint(0xC72D);
+
+    // C5F5 LDX, C5F7 STX, C5F9 STX, C5FB STX, C5FD JSR $C72D
+    assert_eq!(nes.run_instructions(100), StopReason::Breakpoint(0xC72D));
+    assert_eq!(nes.get_pc(), 0xC72D);
+    let cycles = nes.get_cycle_count();
+
+    // Resuming executes the instruction at the breakpoint
+    assert_eq!(nes.run_instructions(1), StopReason::Completed);
+    assert_eq!(nes.get_pc(), 0xC72E);
+    assert_eq!(nes.get_cycle_count() - cycles, 2);
+
+    nes.remove_breakpoint(0xC72D);
+    assert!(nes.get_breakpoints().is_empty());
+}
+
+#[test]
+fn run_until_checks_after_every_frame() {
+    // Keeps enabling NMIs (ignored during the warm-up), the NMI handler increments $10
+    let mut nes = test_nes(&[
+        (0x8000, &[0xA9, 0x80, 0x8D, 0x00, 0x20, 0x4C, 0x00, 0x80]),
+        (0x8100, &[0xE6, 0x10, 0x40]),
+    ]);
+
+    assert!(nes.run_until(10, |nes| nes.peek(0x10) == 3));
+    assert_eq!(nes.peek(0x10), 3);
+    let frames = nes.get_frame_count();
+    assert!(frames < 10);
+
+    assert!(!nes.run_until(2, |nes| nes.peek(0x10) == 0));
+    assert_eq!(nes.get_frame_count(), frames + 2);
+}
+
+/// xorshift64*
+fn next_random(state: &mut u64) -> u8 {
+    *state ^= *state >> 12;
+    *state ^= *state << 25;
+    *state ^= *state >> 27;
+    (state.wrapping_mul(0x2545F4914F6CDD1D) >> 56) as u8
+}
+
+/// Runs random programs from random addresses, only checking that the emulator doesn't panic
+#[test]
+fn cpu_fuzz() {
+    const RUNS: u64 = 128;
+    const CYCLES: u64 = 100_000;
+
+    for seed in 1..=RUNS {
+        let result = panic::catch_unwind(|| {
+            let mut state = seed;
+
+            // Random code, data and interrupt vectors
+            let prg: Vec<u8> = (0..0x4000).map(|_| next_random(&mut state)).collect();
+            let rom = test_rom(&[(0x8000, &prg)]);
+
+            let mut nes = NesBuilder::new()
+                .ram_init(RamInit::Random(seed))
+                .build_from_bytes(&rom)
+                .unwrap();
+            nes.cpu_tick();
+            let pc = u16::from_le_bytes([next_random(&mut state), next_random(&mut state)]);
+            nes.set_pc(pc);
+
+            // KIL opcodes jam the CPU, the rest of the console keeps running
+            while nes.get_cycle_count() < CYCLES {
+                nes.cpu_tick();
+            }
+        });
+
+        assert!(result.is_ok(), "CPU fuzzing failed with seed {}", seed);
+    }
+}
+
+#[test]
+fn kil_jams_the_cpu() {
+    // INX, KIL, INX
+    let mut nes = test_nes(&[(0x8000, &[0xE8, 0x02, 0xE8])]);
+    assert_eq!(nes.run_instructions(10), StopReason::CpuJammed);
+    assert!(nes.is_cpu_jammed());
+    assert_eq!(nes.get_pc(), 0x8001);
+    assert_eq!(nes.debug_snapshot().cpu.x, 1);
+
+    // Only the CPU is halted
+    let frames = nes.get_frame_count();
+    assert_eq!(nes.run_one_frame(), StopReason::CpuJammed);
+    assert_eq!(nes.get_frame_count(), frames);
+    let (cycles, dots) = (nes.get_cycle_count(), nes.ppu_dot_count());
+    assert_eq!(nes.run_instructions(5), StopReason::CpuJammed);
+    assert_eq!(nes.get_cycle_count(), cycles + 1);
+    assert_eq!(nes.ppu_dot_count(), dots + 3);
+    assert_eq!(nes.debug_snapshot().cpu.x, 1);
+
+    // The reset sequence and the first INX
+    nes.reset();
+    assert!(!nes.is_cpu_jammed());
+    assert_eq!(nes.run_instructions(2), StopReason::Completed);
+    assert_eq!(nes.get_pc(), 0x8001);
+    assert_eq!(nes.debug_snapshot().cpu.x, 2);
+}
+
+#[test]
+fn decimal_flag_is_ignored() {
+    #[rustfmt::skip]
+    let mut nes = test_nes(&[(0x8000, &[
+        // SED, CLC, LDA #$09, ADC #$01, STA $10
+        0xF8, 0x18, 0xA9, 0x09, 0x69, 0x01, 0x85, 0x10,
+        // SEC, LDA #$10, SBC #$01, STA $11
+        0x38, 0xA9, 0x10, 0xE9, 0x01, 0x85, 0x11,
+        // PHP, PLA, STA $12, CLD, PHP, PLA, STA $13
+        0x08, 0x68, 0x85, 0x12, 0xD8, 0x08, 0x68, 0x85, 0x13,
+        // LDA #$08, PHA, PLP, JMP *
+        0xA9, 0x08, 0x48, 0x28, 0x4C, 0x1C, 0x80,
+    ])]);
+    nes.run_one_frame();
+
+    // $10 and $09 in BCD
+    assert_eq!(nes.peek(0x10), 0x0A);
+    assert_eq!(nes.peek(0x11), 0x0F);
+
+    // SED, CLD and PLP still change the flag
+    assert_ne!(nes.peek(0x12) & 0x08, 0);
+    assert_eq!(nes.peek(0x13) & 0x08, 0);
+    assert_ne!(nes.debug_snapshot().cpu.p & 0x08, 0);
+}
+
+#[test]
+fn apu_test_registers_read_open_bus() {
+    // LDA $401F, STA $10, JMP $8005
+    let mut nes = test_nes(&[(0x8000, &[0xAD, 0x1F, 0x40, 0x85, 0x10, 0x4C, 0x05, 0x80])]);
+    nes.cpu_write(0x00, 0x5A);
+    nes.cpu_read(0x00);
+    for addr in 0x4018..=0x401F {
+        assert_eq!(nes.cpu_read(addr), 0x5A);
+    }
+
+    // Writes are ignored
+    let registers = nes.apu_register_shadow();
+    nes.cpu_write(0x401F, 0xFF);
+    assert_eq!(nes.apu_register_shadow(), registers);
+
+    // The last value on the bus is the high byte of the address
+    nes.run_one_frame();
+    assert_eq!(nes.peek(0x10), 0x40);
+}
+
+#[test]
+fn rmw_instructions_write_twice() {
+    // INC $4018, LDX #$00, LSR $4018,X
+    let mut nes = test_nes(&[(0x8000, &[0xEE, 0x18, 0x40, 0xA2, 0x00, 0x5E, 0x18, 0x40])]);
+
+    let writes = Arc::new(Mutex::new(Vec::new()));
+    let hook_writes = Arc::clone(&writes);
+    nes.set_unimplemented_write_hook(Box::new(move |addr, val| {
+        hook_writes.lock().unwrap().push((addr, val))
+    }));
+    nes.run_instructions(4);
+
+    // The unmapped register reads back the high byte of the address (open bus), the unmodified
+    // value is written back before the result
+    assert_eq!(
+        *writes.lock().unwrap(),
+        [
+            (0x4018, 0x40),
+            (0x4018, 0x41),
+            (0x4018, 0x40),
+            (0x4018, 0x20)
+        ]
+    );
+}
+
+/// CPU cycles and audio samples of 10 frames
+fn overclocked_frames(overclock: u16) -> (u64, usize) {
+    let mut nes = test_nes(&[]);
+    nes.set_overclock_scanlines(overclock);
+    nes.run_one_frame();
+    nes.clear_audio_buffer();
+
+    let start = nes.get_cycle_count();
+    for _ in 0..10 {
+        nes.run_one_frame();
+    }
+    assert_eq!(nes.get_frame_count(), 11);
+
+    (nes.get_cycle_count() - start, nes.get_audio_buffer().len())
+}
+
+#[test]
+fn overclock_adds_cpu_cycles() {
+    let (cycles, samples) = overclocked_frames(0);
+    let (overclocked_cycles, overclocked_samples) = overclocked_frames(20);
+
+    // 20 * 341 / 3 extra cycles per frame, frames end on instruction boundaries
+    let extra = overclocked_cycles - cycles;
+    assert!(
+        (10 * 2273 - 5..=10 * 2273 + 5).contains(&extra),
+        "{}",
+        extra
+    );
+    assert_eq!(overclocked_samples, samples);
+}
+
+#[test]
+fn overclock_doesnt_shorten_ppu_warm_up() {
+    let warm_up_dot = |overclock: u16| {
+        let mut nes = test_nes(&[]);
+        nes.set_overclock_scanlines(overclock);
+        while !nes.ppu_warmed_up() {
+            nes.clock_ppu_apu();
+        }
+        nes.ppu_dot_count()
+    };
+
+    // The first extra scanlines are inserted before the end of the warm-up
+    assert_eq!(warm_up_dot(20), warm_up_dot(0));
+}
+
+#[test]
+fn trigger_nmi_runs_handler() {
+    // The NMI handler stores $42 to $10
+    let mut nes = test_nes(&[(0x8100, &[0xA9, 0x42, 0x85, 0x10, 0x40])]);
+    nes.run_instructions(10);
+
+    nes.add_breakpoint(0x8100);
+    nes.trigger_nmi();
+    assert_eq!(nes.run_instructions(10), StopReason::Breakpoint(0x8100));
+    assert_eq!(nes.get_pc(), 0x8100);
+
+    nes.remove_breakpoint(0x8100);
+    nes.run_instructions(3);
+    assert_eq!(nes.peek(0x10), 0x42);
+    assert_eq!(nes.get_pc(), 0x8000);
+}
+
+#[test]
+fn assert_irq_holds_the_line() {
+    // CLI, then spin
+    let mut nes = test_nes(&[(0x8000, &[0x58, 0x4C, 0x01, 0x80])]);
+    nes.run_instructions(5);
+    nes.add_breakpoint(0x8200);
+
+    for source in [
+        IrqSource::External,
+        IrqSource::Mapper,
+        IrqSource::FrameCounter,
+    ] {
+        nes.assert_irq(source);
+        assert_eq!(nes.run_instructions(10), StopReason::Breakpoint(0x8200));
+        // The handler doesn't acknowledge the IRQ, so it's taken again right after the RTI
+        assert_eq!(nes.run_instructions(10), StopReason::Breakpoint(0x8200));
+
+        nes.clear_irq(source);
+        assert_eq!(nes.run_instructions(10), StopReason::Completed);
+    }
+}
+
+/// The first lines of nestest.log
+const NESTEST_LOG_START: &str = "\
+C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7
+C5F5  A2 00     LDX #$00                        A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 30 CYC:10
+C5F7  86 00     STX $00 = 00                    A:00 X:00 Y:00 P:26 SP:FD PPU:  0, 36 CYC:12
+C5F9  86 10     STX $10 = 00                    A:00 X:00 Y:00 P:26 SP:FD PPU:  0, 45 CYC:15
+C5FB  86 11     STX $11 = 00                    A:00 X:00 Y:00 P:26 SP:FD PPU:  0, 54 CYC:18
+C5FD  20 2D C7  JSR $C72D                       A:00 X:00 Y:00 P:26 SP:FD PPU:  0, 63 CYC:21
+C72D  EA        NOP                             A:00 X:00 Y:00 P:26 SP:FB PPU:  0, 81 CYC:27
+C72E  38        SEC                             A:00 X:00 Y:00 P:26 SP:FB PPU:  0, 87 CYC:29
+C72F  B0 04     BCS $C735                       A:00 X:00 Y:00 P:27 SP:FB PPU:  0, 93 CYC:31
+C735  EA        NOP                             A:00 X:00 Y:00 P:27 SP:FB PPU:  0,102 CYC:34
+C736  18        CLC                             A:00 X:00 Y:00 P:27 SP:FB PPU:  0,108 CYC:36
+";
+
+/// PC, PPU position and CYC of the last lines of nestest.log, starting at line 8987
+const NESTEST_LOG_END: &str = "\
+C69A  PPU:233,161 CYC:26538
+C69D  PPU:233,173 CYC:26542
+C69F  PPU:233,179 CYC:26544
+C6A2  PPU:233,191 CYC:26548
+C66E  PPU:233,209 CYC:26554
+";
+
+#[test]
+fn nestest_log() {
+    let mut nes = nestest();
+    run_and_compare_log(&mut nes, NESTEST_LOG_START, usize::MAX);
+
+    nes.run_instructions(8986 - NESTEST_LOG_START.lines().count() as u64);
+    run_and_compare_log(&mut nes, NESTEST_LOG_END, usize::MAX);
+}
+
+#[test]
+fn nestest_timing() {
+    let mut nes = nestest();
+    for _ in 0..8990 {
+        let cyc = nes.get_cycle_count();
+        assert_eq!(nes.ppu_dot_count(), 3 * cyc);
+        assert_eq!(nes.master_cycle_count(), 12 * cyc);
+        nes.run_instructions(1);
+    }
+}
+
+#[test]
+fn log_origin_can_be_moved() {
+    let log = "\
+C000  PPU:100, 21 CYC:1007
+C5F5  PPU:100, 30 CYC:1010
+C5F7  PPU:100, 36 CYC:1012
+";
+
+    let mut nes = nestest();
+    nes.set_cycle_origin(1007);
+    nes.set_ppu_origin(100, 21).unwrap();
+    run_and_compare_log(&mut nes, log, usize::MAX);
+    // The origin only moves the CPU cycle count
+    assert_eq!(nes.master_cycle_count(), 4 * nes.ppu_dot_count());
+
+    assert!(matches!(
+        nes.set_ppu_origin(262, 0),
+        Err(NesError::InvalidPpuPosition {
+            scanline: 262,
+            dot: 0
+        })
+    ));
+    assert!(nes.set_ppu_origin(261, 341).is_err());
+    assert_eq!(nes.ppu_position(), (100, 45));
+
+    // Power cycling resets the origin
+    nes.power_cycle();
+    assert_eq!(nes.get_cycle_count(), 0);
+}
+
+#[test]
+fn opcode_profile_counts_every_cycle() {
+    let rom = fs::read(test_path("cpu/nestest/nestest.nes")).unwrap();
+    let mut nes = Nes::new(&rom).expect("error when creating test NES instance");
+    assert_eq!(nes.opcode_profile(), None);
+
+    nes.enable_opcode_profiling();
+    nes.cpu_tick();
+    nes.set_pc(0xC000);
+    nes.run_instructions(8990);
+
+    let profile = nes.opcode_profile().unwrap();
+    assert_eq!(profile.cycles.iter().sum::<u64>(), nes.get_cycle_count());
+    // The reset sequence counts as BRK
+    assert_eq!(profile.executions.iter().sum::<u64>(), 8991);
+    assert!(profile.executions[0xEA] > 0);
+    assert_eq!(profile.cycles[0xEA], 2 * profile.executions[0xEA]);
+
+    nes.disable_opcode_profiling();
+    assert_eq!(nes.opcode_profile(), None);
+}
+
+#[test]
+#[should_panic(expected = "log mismatch at line 3")]
+fn compare_log_reports_the_first_mismatch() {
+    let log = NESTEST_LOG_START.replace("P:26 SP:FD PPU:  0, 36", "P:24 SP:FD PPU:  0, 36");
+    run_and_compare_log(&mut nestest(), &log, 5);
+}
+
+#[rustfmt::skip]
+const NESTED_CALLS: &[(u16, &[u8])] = &[
+    (0x8000, &[
+        0x20, 0x10, 0x80, // JSR $8010
+        0xEA,             // NOP
+        0x4C, 0x04, 0x80, // JMP $8004
+    ]),
+    (0x8010, &[
+        0x20, 0x20, 0x80, // JSR $8020
+        0xE8,             // INX
+        0x60,             // RTS
+    ]),
+    (0x8020, &[
+        0xC8,             // INY
+        0x60,             // RTS
+    ]),
+];
+
+#[rustfmt::skip]
+const ENDLESS_CALL: &[(u16, &[u8])] = &[
+    (0x8000, &[
+        0x20, 0x10, 0x80, // JSR $8010
+    ]),
+    (0x8010, &[
+        0x4C, 0x10, 0x80, // JMP $8010
+    ]),
+];
+
+#[test]
+fn step_over_runs_the_whole_subroutine() {
+    let mut nes = test_nes(NESTED_CALLS);
+    // Reset sequence
+    nes.run_instructions(1);
+
+    assert_eq!(nes.step_over(), StopReason::Completed);
+    assert_eq!(nes.get_pc(), 0x8003);
+    let cpu = nes.debug_snapshot().cpu;
+    assert_eq!((cpu.x, cpu.y), (1, 1));
+
+    // Not a JSR
+    nes.step_over();
+    assert_eq!(nes.get_pc(), 0x8004);
+}
+
+#[test]
+fn step_out_returns_to_the_caller() {
+    let mut nes = test_nes(NESTED_CALLS);
+    // Reset, JSR $8010, JSR $8020
+    nes.run_instructions(3);
+    assert_eq!(nes.get_pc(), 0x8020);
+
+    assert_eq!(nes.step_out(), StopReason::Completed);
+    assert_eq!(nes.get_pc(), 0x8013);
+
+    // Skips the nested call
+    let mut nes = test_nes(NESTED_CALLS);
+    nes.run_instructions(2);
+    assert_eq!(nes.step_out(), StopReason::Completed);
+    assert_eq!(nes.get_pc(), 0x8003);
+    let cpu = nes.debug_snapshot().cpu;
+    assert_eq!((cpu.x, cpu.y), (1, 1));
+}
+
+#[test]
+fn stepping_gives_up_when_the_subroutine_never_returns() {
+    let mut nes = test_nes(ENDLESS_CALL);
+    nes.run_instructions(1);
+
+    assert_eq!(nes.step_over(), StopReason::TimedOut);
+    assert_eq!(nes.get_pc(), 0x8010);
+
+    // From the main loop
+    let mut nes = test_nes(NESTED_CALLS);
+    nes.run_instructions(2);
+    nes.step_out();
+    assert_eq!(nes.step_out(), StopReason::TimedOut);
+    assert_eq!(nes.get_pc(), 0x8004);
+}
+
+#[test]
+fn read_range_matches_peek() {
+    let mut nes = test_nes(&[]);
+    let data: Vec<u8> = (0..=255).collect();
+    nes.write_range(0x0780, &data);
+
+    // $0780 - $087F crosses into the first RAM mirror
+    let mut range = [0; 256];
+    nes.read_range(0x0780, 256, &mut range);
+    assert_eq!(range[..], data[..]);
+    for (i, &val) in range.iter().enumerate() {
+        assert_eq!(val, nes.peek(0x0780 + i as u16));
+    }
+
+    // Wraps around from the interrupt vectors to the zero page
+    let mut range = [0; 32];
+    nes.read_range(0xFFF0, 32, &mut range);
+    for (i, &val) in range.iter().enumerate() {
+        assert_eq!(val, nes.peek(0xFFF0u16.wrapping_add(i as u16)));
+    }
+    assert_eq!(range[0xA..0x10], [0x00, 0x81, 0x00, 0x80, 0x00, 0x82]);
+}
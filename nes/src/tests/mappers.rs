@@ -0,0 +1,275 @@
+use super::*;
+use std::sync::{Arc, Mutex};
+
+use crate::{MapperWrite, NesBuilder, StateError};
+
+/// Mapper 21 (VRC4a / VRC4c) ROM with 128KB of PRG ROM and 32KB of CHR ROM, every bank is filled
+/// with its own number
+fn vrc4_nes() -> Nes {
+    let mut rom = vec![
+        0x4E, 0x45, 0x53, 0x1A, 8, 4, 0x50, 0x10, 0, 0, 0, 0, 0, 0, 0, 0,
+    ];
+    for bank in 0..16 {
+        rom.extend_from_slice(&[bank; 0x2000]);
+    }
+
+    let vectors = rom.len() - 6;
+    rom[vectors..].copy_from_slice(&[0x00, 0xE0, 0x00, 0xE0, 0x00, 0xE0]);
+
+    for bank in 0..32 {
+        rom.extend_from_slice(&[bank; 0x400]);
+    }
+
+    Nes::new(&rom).expect("error when creating test NES instance")
+}
+
+/// Returns the number of CPU cycles until the mapper asserts the IRQ line
+fn cycles_until_irq(nes: &mut Nes) -> u32 {
+    let mut cycles = 0;
+    while !nes.cpu.irq_signal {
+        nes.clock_ppu_apu();
+        cycles += 1;
+        assert!(cycles < 100_000, "the VRC IRQ never fired");
+    }
+    cycles
+}
+
+#[test]
+fn vrc4_irq_cycle_mode() {
+    let mut nes = vrc4_nes();
+
+    // Latch = $F0, enabled in CPU cycle mode
+    nes.cpu_write(0xF000, 0x0);
+    nes.cpu_write(0xF002, 0xF);
+    nes.cpu_write(0xF004, 0x6);
+
+    // $F0 -> $FF takes 15 cycles, the overflow happens on the 16th
+    assert_eq!(cycles_until_irq(&mut nes), 16);
+
+    // Acknowledging without "enable after acknowledgement" set disables the IRQ
+    nes.cpu_write(0xF006, 0);
+    assert!(!nes.cpu.irq_signal);
+    for _ in 0..100 {
+        nes.clock_ppu_apu();
+    }
+    assert!(!nes.cpu.irq_signal);
+}
+
+#[test]
+fn vrc4_irq_scanline_mode() {
+    let mut nes = vrc4_nes();
+
+    // Latch = $FE, enabled in scanline mode, stays enabled after acknowledgements
+    nes.cpu_write(0xF000, 0xE);
+    nes.cpu_write(0xF002, 0xF);
+    nes.cpu_write(0xF004, 0x3);
+
+    // The prescaler clocks the counter every 113 or 114 CPU cycles
+    assert_eq!(cycles_until_irq(&mut nes), 228);
+
+    nes.cpu_write(0xF006, 0);
+    assert!(!nes.cpu.irq_signal);
+    // Reloaded from the latch, so 2 more scanlines
+    let cycles = cycles_until_irq(&mut nes);
+    assert!((227..=228).contains(&cycles), "{}", cycles);
+}
+
+#[test]
+fn vrc4_bank_switching() {
+    let mut nes = vrc4_nes();
+
+    assert_eq!(nes.cpu_read(0xC000), 14);
+    assert_eq!(nes.cpu_read(0xE000), 15);
+
+    nes.cpu_write(0x8000, 3);
+    nes.cpu_write(0xA000, 7);
+    assert_eq!(nes.cpu_read(0x8000), 3);
+    assert_eq!(nes.cpu_read(0xA000), 7);
+
+    // PRG swap mode
+    nes.cpu_write(0x9004, 2);
+    assert_eq!(nes.cpu_read(0x8000), 14);
+    assert_eq!(nes.cpu_read(0xA000), 7);
+    assert_eq!(nes.cpu_read(0xC000), 3);
+    assert_eq!(nes.cpu_read(0xE000), 15);
+
+    // CHR bank 0 = $15, low nibble then high bits
+    nes.cpu_write(0xB000, 5);
+    nes.cpu_write(0xB002, 1);
+    assert_eq!(nes.mapper.read_chr(0x0000), 21);
+    // CHR bank 7
+    nes.cpu_write(0xE004, 9);
+    assert_eq!(nes.mapper.read_chr(0x1C00), 9);
+
+    nes.cpu_write(0x9000, 3);
+    assert!(matches!(
+        nes.mapper.mirroring(),
+        crate::ppu::Mirroring::SingleScreenHigh
+    ));
+}
+
+#[test]
+fn vrc4_reset_restores_power_on_banks() {
+    let mut nes = vrc4_nes();
+    let power_on = nes.mapper.banks();
+
+    nes.cpu_write(0x8000, 3);
+    nes.cpu_write(0x9004, 2);
+    nes.cpu_write(0xB000, 5);
+    assert_ne!(nes.mapper.banks(), power_on);
+
+    nes.mapper.reset();
+    assert_eq!(nes.mapper.banks(), power_on);
+}
+
+#[test]
+fn load_state_rejects_other_mappers() {
+    let mut rom = test_rom(&[]);
+    // Mapper 1
+    rom[6] = 0x10;
+    let mut mmc1 = Nes::new(&rom).expect("error when creating test NES instance");
+    mmc1.run_one_frame();
+    let save = mmc1.save_state().unwrap();
+
+    let mut nrom = test_nes(&[]);
+    let err = nrom.load_state(&save).unwrap_err();
+    assert!(matches!(
+        err,
+        NesError::State(StateError::MapperMismatch {
+            saved: "MMC1",
+            current: "NROM"
+        })
+    ));
+    assert_eq!(nrom.get_frame_count(), 0);
+
+    let mut other_mmc1 = Nes::new(&rom).expect("error when creating test NES instance");
+    other_mmc1.load_state(&save).unwrap();
+    assert_eq!(other_mmc1.get_frame_count(), 1);
+}
+
+#[test]
+fn load_state_compares_the_mapper_chip() {
+    let mut rom = test_rom(&[]);
+    // Mapper 21 (VRC4a / VRC4c)
+    rom[6] = 0x50;
+    rom[7] = 0x10;
+    let mut vrc4 = Nes::new(&rom).expect("error when creating test NES instance");
+    vrc4.run_one_frame();
+    let save = vrc4.save_state().unwrap();
+
+    // Mapper 23 is another wiring of the same chip
+    let mut overridden = NesBuilder::new()
+        .mapper_override(23)
+        .build_from_bytes(&rom)
+        .expect("error when creating test NES instance");
+    overridden.load_state(&save).unwrap();
+    assert_eq!(overridden.get_frame_count(), 1);
+
+    let mut nrom = NesBuilder::new()
+        .mapper_override(0)
+        .build_from_bytes(&rom)
+        .expect("error when creating test NES instance");
+    assert!(matches!(
+        nrom.load_state(&save),
+        Err(NesError::State(StateError::MapperMismatch {
+            saved: "VRC",
+            current: "NROM"
+        }))
+    ));
+}
+
+#[test]
+fn load_state_rejects_other_games() {
+    let mut game_a = test_nes(&[]);
+    game_a.run_one_frame();
+    let save = game_a.save_state().unwrap();
+
+    // Same mapper, different PRG ROM
+    let mut game_b = test_nes(&[(0x8100, &[0x60])]);
+    let err = game_b.load_state(&save).unwrap_err();
+    match err {
+        NesError::State(StateError::CartridgeMismatch { expected, found }) => {
+            assert_eq!(expected, game_b.mapper.cartridge_hash());
+            assert_eq!(found, game_a.mapper.cartridge_hash());
+            assert_ne!(expected, found);
+        }
+        err => panic!("unexpected error: {}", err),
+    }
+    assert_eq!(game_b.get_frame_count(), 0);
+
+    game_b.load_state_force(&save).unwrap();
+    assert_eq!(game_b.get_frame_count(), 1);
+    assert_eq!(game_b.peek(0x8100), 0x40);
+}
+
+#[test]
+fn mapper_write_callback_decodes_mmc1_control() {
+    let mut rom = test_rom(&[]);
+    rom[6] = 0x10;
+    let mut nes = Nes::new(&rom).expect("error when creating test NES instance");
+
+    let writes = Arc::new(Mutex::new(Vec::new()));
+    let log = Arc::clone(&writes);
+    nes.set_mapper_write_callback(Box::new(move |write: &MapperWrite| {
+        log.lock().unwrap().push(write.clone())
+    }));
+
+    // Control = %01110, serially from the lowest bit. The MMC1 ignores writes on consecutive
+    // cycles, so run a cycle in between.
+    for bit in [0, 1, 1, 1, 0] {
+        nes.run_cpu_cycle();
+        nes.cpu_write(0x8000, bit);
+    }
+
+    let writes = writes.lock().unwrap();
+    assert_eq!(writes.len(), 1, "{:?}", writes);
+    assert_eq!(writes[0].addr, 0x8000);
+    assert_eq!(writes[0].register, "MMC1 control");
+    assert_eq!(
+        writes[0].effect,
+        "mirroring=Vertical, PRG mode=3, CHR mode=8KB"
+    );
+}
+
+#[test]
+fn nes_with_hooks_is_send() {
+    fn assert_send<T: Send>() {}
+    assert_send::<Nes>();
+}
+
+#[test]
+fn oversized_bank_selects_wrap() {
+    // UxROM with 64KB of PRG ROM, $9000 of every bank holds the bank number
+    let mut rom = test_rom(&[]);
+    rom[4] = 4;
+    rom[6] = 0x20;
+    let prg = rom[16..16 + 0x4000].to_vec();
+    for _ in 1..4 {
+        rom.splice(16..16, prg.iter().copied());
+    }
+    for bank in 0..4 {
+        rom[16 + bank * 0x4000 + 0x1000] = bank as u8;
+    }
+
+    let mut nes = Nes::new(&rom).expect("error when creating test NES instance");
+    nes.cpu_write(0x8000, 2);
+    assert_eq!(nes.peek(0x9000), 2);
+    nes.cpu_write(0x8000, 6);
+    assert_eq!(nes.peek(0x9000), 2);
+    nes.cpu_write(0x8000, 0xFF);
+    assert_eq!(nes.peek(0x9000), 3);
+}
+
+#[test]
+fn invalid_rom_sizes_are_rejected() {
+    // AxROM only switches 32KB banks
+    let mut rom = test_rom(&[]);
+    rom[6] = 0x70;
+    assert!(matches!(Nes::new(&rom), Err(NesError::InvalidRomSize(7))));
+
+    // MMC3 always maps the last 2 8KB banks
+    let mut rom = test_rom(&[]);
+    rom[4] = 0;
+    rom[6] = 0x40;
+    assert!(matches!(Nes::new(&rom), Err(NesError::InvalidRomSize(4))));
+}
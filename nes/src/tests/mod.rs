@@ -1,21 +1,35 @@
-use std::{env, fs, hash::Hasher, path::Path};
+use std::{
+    collections::VecDeque,
+    env, fs,
+    hash::Hasher,
+    path::{Path, PathBuf},
+};
 
 use siphasher::sip::SipHasher13;
 
-use super::Nes;
+use super::{Nes, NesError, StopReason};
 
+mod apu;
+mod builder;
+mod cartridge;
+mod controller;
 mod cpu;
 
 #[cfg(feature = "integration_tests")]
 mod integration;
+mod mappers;
+mod pacer;
+mod patch;
 mod ppu;
+mod snapshot;
 
-fn blargg_test(rom_path: &str, pass_text: &str) {
+fn test_path(path: &str) -> PathBuf {
     let base_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
-    let test_path = "/src/tests/";
-    let rom_path = base_dir + test_path + rom_path;
+    Path::new(&base_dir).join("src/tests").join(path)
+}
 
-    let rom = fs::read(Path::new(&rom_path)).unwrap();
+fn blargg_test(rom_path: &str, pass_text: &str) {
+    let rom = fs::read(test_path(rom_path)).unwrap();
 
     let mut nes = Nes::new(&rom).expect("error when creating test NES instance");
 
@@ -45,11 +59,7 @@ fn blargg_test(rom_path: &str, pass_text: &str) {
 }
 
 fn hash_test(rom_path: &str, frames_to_run: u64, expected_hash: u64) {
-    let base_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
-    let test_path = "/src/tests/";
-    let rom_path = base_dir + test_path + rom_path;
-
-    let rom = fs::read(Path::new(&rom_path)).unwrap();
+    let rom = fs::read(test_path(rom_path)).unwrap();
 
     let mut nes = Nes::new(&rom).expect("error when creating test NES instance");
 
@@ -62,3 +72,107 @@ fn hash_test(rom_path: &str, frames_to_run: u64, expected_hash: u64) {
     hasher.write(nes.get_frame_buffer());
     assert_eq!(hasher.finish(), expected_hash);
 }
+
+/// Builds an iNES image for small hand-assembled test programs: mapper 0, 16KB of PRG-ROM
+/// (mirrored at $8000 and $C000) and 8KB of CHR-RAM.
+/// The reset vector points to $8000, the NMI vector to $8100 and the IRQ vector to $8200.
+/// By default the reset code spins on `JMP $8000` and both interrupt handlers are a lone `RTI`;
+/// `segments` patches code or data in at the given CPU addresses.
+fn test_rom(segments: &[(u16, &[u8])]) -> Vec<u8> {
+    let mut prg = vec![0xEA; 0x4000];
+    prg[0x0000..0x0003].copy_from_slice(&[0x4C, 0x00, 0x80]);
+    prg[0x0100] = 0x40;
+    prg[0x0200] = 0x40;
+    prg[0x3FFA..].copy_from_slice(&[0x00, 0x81, 0x00, 0x80, 0x00, 0x82]);
+
+    for (addr, bytes) in segments {
+        let start = usize::from(*addr) & 0x3FFF;
+        prg[start..start + bytes.len()].copy_from_slice(bytes);
+    }
+
+    let mut rom = vec![0x4E, 0x45, 0x53, 0x1A, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+    rom.extend_from_slice(&prg);
+    rom
+}
+
+fn test_nes(segments: &[(u16, &[u8])]) -> Nes {
+    Nes::new(&test_rom(segments)).expect("error when creating test NES instance")
+}
+
+/// [`test_nes`] run until the PPU accepts writes to all of its registers
+fn warmed_up_test_nes(segments: &[(u16, &[u8])]) -> Nes {
+    let mut nes = test_nes(segments);
+    while !nes.ppu_warmed_up() {
+        nes.run_cpu_cycle();
+    }
+    nes
+}
+
+/// Runs the PPU alone until it is about to process the given dot
+fn step_ppu_to(nes: &mut Nes, scanline: u16, xpos: u16) {
+    while nes.ppu.scanline != scanline || nes.ppu.xpos != xpos {
+        nes.ppu_tick();
+    }
+}
+
+/// The CPU state before the next instruction in the nestest.log format, without the disassembly
+fn log_line(nes: &Nes) -> String {
+    let snapshot = nes.debug_snapshot();
+    let (cpu, ppu) = (snapshot.cpu, snapshot.ppu);
+    format!(
+        "{:04X}  A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} PPU:{:3},{:3} CYC:{}",
+        cpu.pc, cpu.a, cpu.x, cpu.y, cpu.p, cpu.sp, ppu.scanline, ppu.xpos, cpu.cycle_count
+    )
+}
+
+/// The value of a `KEY:value` field of a log line, spaces in the PPU position are removed
+fn log_field(line: &str, key: &str) -> Option<String> {
+    let start = line.find(&format!(" {}:", key))? + key.len() + 2;
+    let value = &line[start..];
+
+    let value = if key == "PPU" {
+        let end = value.find(" CYC:").unwrap_or(value.len());
+        value[..end].replace(' ', "")
+    } else {
+        value.split_whitespace().next().unwrap_or("").to_string()
+    };
+    Some(value)
+}
+
+/// Compares the PC and the fields present in `expected`, so reference logs don't need all of them
+fn log_line_matches(expected: &str, actual: &str) -> bool {
+    expected.split_whitespace().next() == actual.split_whitespace().next()
+        && ["A", "X", "Y", "P", "SP", "PPU", "CYC"].iter().all(|key| {
+            let field = log_field(expected, key);
+            field.is_none() || field == log_field(actual, key)
+        })
+}
+
+/// Runs one instruction per line of `reference_log` (at most `max_lines`), after comparing the
+/// state before the instruction with the line. The lines use the nestest.log format:
+/// `C000  4C F5 C5  JMP $C5F5  A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7`.
+/// Panics at the first mismatch, with the previous lines for context.
+fn run_and_compare_log(nes: &mut Nes, reference_log: &str, max_lines: usize) {
+    const CONTEXT: usize = 5;
+    let mut previous = VecDeque::with_capacity(CONTEXT);
+
+    for (i, expected) in reference_log.lines().take(max_lines).enumerate() {
+        let actual = log_line(nes);
+        if !log_line_matches(expected, &actual) {
+            let context: Vec<&str> = previous.iter().map(String::as_str).collect();
+            panic!(
+                "log mismatch at line {}\n{}\nexpected: {}\nactual:   {}",
+                i + 1,
+                context.join("\n"),
+                expected.trim_end(),
+                actual
+            );
+        }
+
+        if previous.len() == CONTEXT {
+            previous.pop_front();
+        }
+        previous.push_back(format!("{:>8}  {}", i + 1, actual));
+        nes.run_instructions(1);
+    }
+}
@@ -0,0 +1,33 @@
+use std::time::{Duration, Instant};
+
+use crate::{FramePacer, Region};
+
+#[test]
+fn ntsc_frame_duration() {
+    let pacer = FramePacer::new(Region::Ntsc);
+
+    // 60.0988 Hz
+    let millis = pacer.frame_duration().as_secs_f64() * 1000.;
+    assert!((millis - 16.639).abs() < 0.001, "{}", millis);
+}
+
+#[test]
+fn pal_frame_duration() {
+    let pacer = FramePacer::new(Region::Pal);
+
+    // 50.007 Hz
+    let millis = pacer.frame_duration().as_secs_f64() * 1000.;
+    assert!((millis - 19.997).abs() < 0.001, "{}", millis);
+}
+
+#[test]
+fn fast_forward_doesnt_sleep() {
+    let mut pacer = FramePacer::new(Region::Ntsc);
+    pacer.set_fast_forward(true);
+
+    let start = Instant::now();
+    for _ in 0..10 {
+        assert_eq!(pacer.wait_for_next_frame(), 0);
+    }
+    assert!(start.elapsed() < Duration::from_millis(16));
+}
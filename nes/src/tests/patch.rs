@@ -0,0 +1,101 @@
+use super::*;
+use crate::{patch::crc32, NesBuilder};
+
+/// A file in the temp directory, removed when it's dropped (also when an assertion fails)
+struct TempFile(PathBuf);
+
+impl TempFile {
+    fn new(name: &str, bytes: &[u8]) -> TempFile {
+        let path = env::temp_dir().join(name);
+        fs::write(&path, bytes).unwrap();
+        TempFile(path)
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+#[test]
+fn ips_patch_changes_rom_bytes() {
+    let rom = TempFile::new("fearless_nes_ips.nes", &test_rom(&[]));
+    #[rustfmt::skip]
+    let patch = TempFile::new("fearless_nes_ips.ips", &[
+        b'P', b'A', b'T', b'C', b'H',
+        // $8010 - $8011 (after the 16 byte header)
+        0x00, 0x00, 0x20, 0x00, 0x02, 0xAB, 0xCD,
+        // 4 times $EE at $8020
+        0x00, 0x00, 0x30, 0x00, 0x00, 0x00, 0x04, 0xEE,
+        b'E', b'O', b'F',
+    ]);
+
+    let nes = Nes::new_patched(&rom.0, &patch.0).unwrap();
+    assert_eq!(nes.peek(0x8010), 0xAB);
+    assert_eq!(nes.peek(0x8011), 0xCD);
+    assert_eq!(
+        (0x8020..0x8024)
+            .map(|addr| nes.peek(addr))
+            .collect::<Vec<_>>(),
+        [0xEE; 4]
+    );
+}
+
+fn bps_number(mut number: usize, patch: &mut Vec<u8>) {
+    loop {
+        let byte = (number & 0x7F) as u8;
+        number >>= 7;
+        if number == 0 {
+            patch.push(0x80 | byte);
+            return;
+        }
+        patch.push(byte);
+        number -= 1;
+    }
+}
+
+/// A BPS patch replacing the byte at `offset` of `source`
+fn bps_patch(source: &[u8], offset: usize, val: u8) -> Vec<u8> {
+    let mut target = source.to_vec();
+    target[offset] = val;
+
+    let mut patch = b"BPS1".to_vec();
+    for number in [source.len(), target.len(), 0] {
+        bps_number(number, &mut patch);
+    }
+    // Source read, target read and source read actions
+    bps_number((offset - 1) << 2, &mut patch);
+    bps_number(1, &mut patch);
+    patch.push(val);
+    bps_number((source.len() - offset - 2) << 2, &mut patch);
+
+    patch.extend_from_slice(&crc32(source).to_le_bytes());
+    patch.extend_from_slice(&crc32(&target).to_le_bytes());
+    patch.extend_from_slice(&crc32(&patch).to_le_bytes());
+    patch
+}
+
+#[test]
+fn bps_patch_checks_crcs() {
+    let rom = test_rom(&[]);
+    let patch = TempFile::new("fearless_nes_bps.bps", &bps_patch(&rom, 0x20, 0x42));
+    let corrupted = {
+        let mut patch = bps_patch(&rom, 0x20, 0x42);
+        patch[10] ^= 1;
+        TempFile::new("fearless_nes_bps_corrupted.bps", &patch)
+    };
+    let unknown = TempFile::new("fearless_nes_bps_unknown.bps", b"UPS1");
+
+    let build =
+        |rom: &[u8], patch: &TempFile| NesBuilder::new().patch(&patch.0).build_from_bytes(rom);
+    let patched = build(&rom, &patch);
+    let other_game = build(&test_rom(&[(0x8000, &[0xEA])]), &patch);
+    let corrupted = build(&rom, &corrupted);
+    let unknown = build(&rom, &unknown);
+
+    assert_eq!(patched.unwrap().peek(0x8010), 0x42);
+    assert!(matches!(other_game, Err(NesError::PatchMismatch)));
+    assert!(matches!(corrupted, Err(NesError::InvalidPatch)));
+    assert!(matches!(unknown, Err(NesError::InvalidPatch)));
+}
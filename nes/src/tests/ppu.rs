@@ -1,4 +1,5 @@
 use super::*;
+use crate::{NAMETABLE_HEIGHT, NAMETABLE_WIDTH, PALETTE, PATTERN_TABLE_SIZE};
 
 //TODO: get read_buffer test working
 //TODO: implement oamtest3 - iNES 2.0 needed
@@ -26,6 +27,21 @@ fn ppu_vbl_nmi_clear_time() {
     );
 }
 
+#[test]
+fn vbl_flag_cleared_at_pre_render_dot_1() {
+    let mut nes = test_nes(&[]);
+
+    step_ppu_to(&mut nes, 261, 0);
+
+    // Dot 0 of the pre-render scanline keeps the flag
+    nes.ppu_tick();
+    assert_eq!(nes.debug_snapshot().ppu.status & 0x80, 0x80);
+
+    // Dot 1 clears it
+    nes.ppu_tick();
+    assert_eq!(nes.debug_snapshot().ppu.status & 0x80, 0);
+}
+
 #[test]
 fn ppu_vbl_nmi_control() {
     blargg_test(
@@ -324,3 +340,591 @@ fn vbl_nmi_timing_nmi_disable() {
 fn vbl_nmi_timing_nmi_timing() {
     hash_test("ppu/vbl_nmi_timing/7.nmi_timing.nes", 180, 0);
 }
+
+#[test]
+fn ppu_ignores_ctrl_writes_during_warm_up() {
+    // The NMI handler counts NMIs at $10
+    let mut nes = test_nes(&[(0x8100, &[0xE6, 0x10, 0x40])]);
+
+    assert!(!nes.ppu_warmed_up());
+    nes.cpu_write(0x2000, 0x80);
+    while !nes.ppu_warmed_up() {
+        nes.run_cpu_cycle();
+    }
+    nes.run_one_frame();
+    nes.run_one_frame();
+    assert_eq!(nes.cpu_read(0x10), 0);
+
+    nes.cpu_write(0x2000, 0x80);
+    nes.run_one_frame();
+    nes.run_one_frame();
+    assert_eq!(nes.cpu_read(0x10), 2);
+}
+
+fn write_vram(nes: &mut Nes, addr: u16, val: u8) {
+    nes.cpu_write(0x2006, (addr >> 8) as u8);
+    nes.cpu_write(0x2006, addr as u8);
+    nes.cpu_write(0x2007, val);
+}
+
+#[test]
+fn pattern_table_cache_invalidated_by_chr_writes() {
+    let mut nes = warmed_up_test_nes(&[]);
+
+    let blank = nes.ppu_pattern_table(0).to_vec();
+    assert_eq!(blank.len(), PATTERN_TABLE_SIZE * PATTERN_TABLE_SIZE * 3);
+    assert!(nes.ppu.debug_cache.pattern_table_cached(0));
+    assert!(!nes.ppu.debug_cache.pattern_table_cached(1));
+
+    nes.run_one_frame();
+    assert!(nes.ppu.debug_cache.pattern_table_cached(0));
+
+    nes.cpu_write(0x6000, 0xFF);
+    assert!(nes.ppu.debug_cache.pattern_table_cached(0));
+
+    // Low bitplane of the first row of tile 0
+    write_vram(&mut nes, 0x0000, 0xFF);
+    assert!(!nes.ppu.debug_cache.pattern_table_cached(0));
+
+    let updated = nes.ppu_pattern_table(0).to_vec();
+    assert_ne!(updated[..3], blank[..3]);
+    assert_eq!(updated[8 * 3..], blank[8 * 3..]);
+}
+
+#[test]
+fn nametable_cache_invalidated_by_nametable_writes() {
+    let mut nes = warmed_up_test_nes(&[]);
+
+    // Tile 1 is a solid block of color 1
+    for row in 0..8 {
+        write_vram(&mut nes, 0x0010 + row, 0xFF);
+    }
+    write_vram(&mut nes, 0x3F01, 0x16);
+
+    let blank = nes.ppu_nametable(0).to_vec();
+    assert_eq!(blank.len(), NAMETABLE_WIDTH * NAMETABLE_HEIGHT * 3);
+    nes.run_one_frame();
+    assert!(nes.ppu.debug_cache.nametable_cached(0));
+
+    write_vram(&mut nes, 0x2000, 0x01);
+    assert!(!nes.ppu.debug_cache.nametable_cached(0));
+
+    let updated = nes.ppu_nametable(0).to_vec();
+    assert_eq!(updated[..3], PALETTE[0x16 * 3..0x16 * 3 + 3]);
+    assert_eq!(
+        updated[NAMETABLE_WIDTH * 8 * 3..],
+        blank[NAMETABLE_WIDTH * 8 * 3..]
+    );
+}
+
+#[test]
+fn current_palettes_use_the_master_palette() {
+    let mut nes = warmed_up_test_nes(&[]);
+
+    write_vram(&mut nes, 0x3F00, 0x0F);
+    write_vram(&mut nes, 0x3F05, 0x16);
+    write_vram(&mut nes, 0x3F1F, 0x30);
+    // Mirror of $3F04
+    write_vram(&mut nes, 0x3F14, 0x21);
+
+    let rgb = |color: usize| PALETTE[color * 3..color * 3 + 3].to_vec();
+    let palettes = nes.current_palettes();
+    assert_eq!(palettes[0][0].to_vec(), rgb(0x0F));
+    assert_eq!(palettes[1][1].to_vec(), rgb(0x16));
+    assert_eq!(palettes[7][3].to_vec(), rgb(0x30));
+    assert_eq!(palettes[1][0].to_vec(), rgb(0x21));
+    assert_eq!(palettes[5][0].to_vec(), rgb(0x21));
+}
+
+#[test]
+fn current_palettes_apply_greyscale_and_emphasis() {
+    let mut nes = warmed_up_test_nes(&[]);
+
+    write_vram(&mut nes, 0x3F01, 0x16);
+    write_vram(&mut nes, 0x3F02, 0x30);
+
+    let rgb = |color: usize| PALETTE[color * 3..color * 3 + 3].to_vec();
+
+    // Greyscale
+    nes.cpu_write(0x2001, 0x01);
+    assert_eq!(nes.current_palettes()[0][1].to_vec(), rgb(0x10));
+
+    // Emphasizing red darkens green and blue
+    nes.cpu_write(0x2001, 0x20);
+    let [r, g, b] = nes.current_palettes()[0][2];
+    let white = rgb(0x30);
+    assert_eq!(r, white[0]);
+    assert!(g < white[1] && b < white[2]);
+
+    // Emphasizing all three colors darkens every channel
+    nes.cpu_write(0x2001, 0xE0);
+    let dimmed = nes.current_palettes()[0][2];
+    assert!((0..3).all(|channel| dimmed[channel] < white[channel]));
+}
+
+#[test]
+fn debug_backdrop_overrides_transparent_nametable_pixels() {
+    let mut nes = warmed_up_test_nes(&[]);
+
+    // Tile 1 is a solid block of color 1, the backdrop is black
+    for row in 0..8 {
+        write_vram(&mut nes, 0x0010 + row, 0xFF);
+    }
+    write_vram(&mut nes, 0x3F00, 0x0F);
+    write_vram(&mut nes, 0x3F01, 0x16);
+    write_vram(&mut nes, 0x2000, 0x01);
+
+    let magenta = [0xFF, 0x00, 0xFF];
+    nes.set_debug_backdrop(Some(magenta));
+    let image = nes.ppu_nametable(0).to_vec();
+    assert_eq!(image[..3], PALETTE[0x16 * 3..0x16 * 3 + 3]);
+    assert_eq!(image[8 * 3..8 * 3 + 3], magenta);
+    assert_eq!(image[image.len() - 3..], magenta);
+
+    nes.set_debug_backdrop(None);
+    let image = nes.ppu_nametable(0).to_vec();
+    assert_eq!(image[8 * 3..8 * 3 + 3], PALETTE[0x0F * 3..0x0F * 3 + 3]);
+}
+
+#[test]
+fn ppu_registers_mirrored() {
+    let mut nes = warmed_up_test_nes(&[]);
+
+    // $3FFE/$3FFF mirror $2006/$2007
+    nes.cpu_write(0x3FFE, 0x3F);
+    nes.cpu_write(0x3FFE, 0x01);
+    nes.cpu_write(0x3FFF, 0x16);
+
+    // Greyscale through $3FF9, a mirror of $2001, affects palette reads
+    nes.cpu_write(0x3FF9, 0x01);
+    nes.cpu_write(0x200E, 0x3F);
+    nes.cpu_write(0x200E, 0x01);
+    assert_eq!(nes.cpu_read(0x200F), 0x10);
+
+    // Reading $3FFA, a mirror of $2002, resets the $2006 write toggle
+    nes.cpu_write(0x2009, 0x00);
+    nes.cpu_write(0x2FFE, 0x12);
+    nes.cpu_read(0x3FFA);
+    nes.cpu_write(0x2006, 0x3F);
+    nes.cpu_write(0x2006, 0x01);
+    assert_eq!(nes.cpu_read(0x2007), 0x16);
+
+    // OAM through the $2003/$2004 mirrors
+    nes.cpu_write(0x2FFB, 0x10);
+    nes.cpu_write(0x2FFC, 0xAB);
+    assert_eq!(nes.ppu.oam[0x10], 0xAB);
+}
+
+#[test]
+fn ram_mirrored() {
+    let mut nes = test_nes(&[]);
+
+    nes.cpu_write(0x0000, 0x42);
+    for mirror in [0x0800, 0x1000, 0x1800] {
+        assert_eq!(nes.cpu_read(mirror), 0x42);
+    }
+
+    nes.cpu_write(0x1FFF, 0x24);
+    assert_eq!(nes.cpu_read(0x07FF), 0x24);
+}
+
+#[test]
+fn screenshot_is_ppm() {
+    let mut nes = test_nes(&[]);
+    nes.ppu.output_buffer[0] = 0x16;
+    nes.ppu.output_buffer[256 * 240 - 1] = 0x30;
+
+    let path = env::temp_dir().join("fearless_nes_screenshot.ppm");
+    nes.save_screenshot(&path).unwrap();
+    let ppm = fs::read(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+
+    let header = b"P6\n256 240\n255\n";
+    assert_eq!(&ppm[..header.len()], header);
+    let pixels = &ppm[header.len()..];
+    assert_eq!(pixels.len(), 256 * 240 * 3);
+    assert_eq!(pixels[..3], PALETTE[0x16 * 3..0x16 * 3 + 3]);
+    assert_eq!(pixels[pixels.len() - 3..], PALETTE[0x30 * 3..0x30 * 3 + 3]);
+}
+
+fn read_palette(nes: &mut Nes, addr: u16) -> u8 {
+    nes.cpu_write(0x2006, (addr >> 8) as u8);
+    nes.cpu_write(0x2006, addr as u8);
+    // Palette reads aren't buffered
+    nes.cpu_read(0x2007)
+}
+
+#[test]
+fn palette_backdrop_mirrors() {
+    let mut nes = warmed_up_test_nes(&[]);
+
+    let mirrors = [
+        (0x3F00, 0x3F10),
+        (0x3F04, 0x3F14),
+        (0x3F08, 0x3F18),
+        (0x3F0C, 0x3F1C),
+    ];
+    for (entry, mirror) in mirrors {
+        write_vram(&mut nes, mirror, 0x21);
+        assert_eq!(read_palette(&mut nes, entry), 0x21);
+
+        write_vram(&mut nes, entry, 0x12);
+        assert_eq!(read_palette(&mut nes, mirror), 0x12);
+    }
+
+    // Other sprite palette entries aren't mirrored
+    write_vram(&mut nes, 0x3F11, 0x05);
+    write_vram(&mut nes, 0x3F01, 0x06);
+    assert_eq!(read_palette(&mut nes, 0x3F11), 0x05);
+    // And the whole palette RAM repeats up to $3FFF
+    assert_eq!(read_palette(&mut nes, 0x3FF0), 0x12);
+    assert_eq!(read_palette(&mut nes, 0x3FE1), 0x06);
+}
+
+/// Runs 10 frames with a different backdrop color each, returns how many of them got drawn and
+/// the number of audio samples
+fn run_colored_frames(nes: &mut Nes) -> (u32, usize) {
+    nes.run_until(10, |nes| nes.ppu_warmed_up());
+    nes.clear_audio_buffer();
+
+    let mut drawn = 0;
+    for color in 0x20..0x2A {
+        write_vram(nes, 0x3F00, color);
+        // Rendering is disabled, so the pixels would show the palette entry at v
+        nes.cpu_write(0x2006, 0);
+        nes.cpu_write(0x2006, 0);
+
+        nes.run_one_frame();
+        if nes.get_frame_buffer()[128 * 256 + 128] == color {
+            drawn += 1;
+        }
+    }
+
+    (drawn, nes.get_audio_buffer().len())
+}
+
+#[test]
+fn frame_skip_keeps_audio() {
+    let (drawn, samples) = run_colored_frames(&mut test_nes(&[]));
+    assert_eq!(drawn, 10);
+    // 10 NTSC frames, a sample every 40 CPU cycles
+    assert!((7440..=7450).contains(&samples), "{}", samples);
+
+    let mut nes = test_nes(&[]);
+    nes.set_frame_skip(1);
+    let (drawn_skip, samples_skip) = run_colored_frames(&mut nes);
+    assert_eq!(drawn_skip, 5);
+    assert_eq!(samples_skip, samples);
+}
+
+#[test]
+fn framebuffer_changed_this_frame_tracks_drawn_frames() {
+    let mut nes = test_nes(&[]);
+    assert!(!nes.framebuffer_changed_this_frame());
+    nes.run_one_frame();
+    assert!(nes.framebuffer_changed_this_frame());
+    // Paused, no frame has been run since the last read
+    assert!(!nes.framebuffer_changed_this_frame());
+
+    nes.set_frame_skip(1);
+    let changed: Vec<bool> = (0..4)
+        .map(|_| {
+            nes.run_one_frame();
+            nes.framebuffer_changed_this_frame()
+        })
+        .collect();
+    assert_eq!(changed.iter().filter(|&&changed| changed).count(), 2);
+
+    let mut nes = crate::NesBuilder::new()
+        .rendering(false)
+        .build_from_bytes(&test_rom(&[]))
+        .unwrap();
+    nes.run_one_frame();
+    assert!(!nes.framebuffer_changed_this_frame());
+}
+
+#[test]
+fn ppustatus_read_clears_write_toggle() {
+    let mut nes = warmed_up_test_nes(&[]);
+
+    nes.cpu_write(0x2006, 0x3F);
+    assert!(nes.debug_snapshot().ppu.write_toggle);
+    nes.cpu_read(0x2002);
+    assert!(!nes.debug_snapshot().ppu.write_toggle);
+
+    nes.cpu_write(0x2006, 0x21);
+    nes.cpu_write(0x2006, 0x08);
+    assert_eq!(nes.debug_snapshot().ppu.vram_addr, 0x2108);
+
+    // Same for $2005
+    nes.cpu_write(0x2005, 0xFF);
+    nes.cpu_read(0x2002);
+    nes.cpu_write(0x2005, 0x0D);
+    let ppu = nes.debug_snapshot().ppu;
+    assert_eq!(ppu.fine_x, 0x5);
+    assert!(ppu.write_toggle);
+}
+
+fn visible_sprites(sprite_limit: bool) -> (usize, bool) {
+    let mut nes = warmed_up_test_nes(&[]);
+    nes.set_sprite_limit_enabled(sprite_limit);
+
+    // Solid tile 1 with a single sprite color
+    for addr in 0x10..0x18 {
+        write_vram(&mut nes, addr, 0xFF);
+    }
+    write_vram(&mut nes, 0x3F00, 0x0F);
+    write_vram(&mut nes, 0x3F11, 0x16);
+
+    // 10 sprites on the same scanlines, every other 8 pixels
+    nes.cpu_write(0x2003, 0);
+    for i in 0..64 {
+        let sprite = if i < 10 {
+            [50, 1, 0, i * 16]
+        } else {
+            [0xFF; 4]
+        };
+        for byte in sprite {
+            nes.cpu_write(0x2004, byte);
+        }
+    }
+
+    nes.cpu_write(0x2006, 0);
+    nes.cpu_write(0x2006, 0);
+    nes.cpu_write(0x2001, 0x14);
+    nes.run_one_frame();
+    nes.run_one_frame();
+    step_ppu_to(&mut nes, 100, 0);
+
+    let row = &nes.get_frame_buffer()[52 * 256..53 * 256];
+    let visible = (0..10).filter(|i| row[i * 16 + 4] == 0x16).count();
+    (visible, nes.debug_snapshot().ppu.status & 0x20 != 0)
+}
+
+#[test]
+fn sprite_limit_can_be_disabled() {
+    assert_eq!(visible_sprites(true), (8, true));
+    // The overflow flag is still set
+    assert_eq!(visible_sprites(false), (10, true));
+}
+
+#[test]
+fn enabling_nmi_during_vblank_fires_it() {
+    // JMP $8000, the NMI handler increments $10
+    let mut nes =
+        warmed_up_test_nes(&[(0x8000, &[0x4C, 0x00, 0x80]), (0x8100, &[0xE6, 0x10, 0x40])]);
+    step_ppu_to(&mut nes, 241, 10);
+    assert_ne!(nes.debug_snapshot().ppu.status & 0x80, 0);
+
+    nes.run_instructions(5);
+    assert_eq!(nes.peek(0x10), 0);
+
+    nes.cpu_write(0x2000, 0x80);
+    nes.run_instructions(5);
+    assert_eq!(nes.peek(0x10), 1);
+
+    // Only another 0 to 1 transition fires it again
+    nes.cpu_write(0x2000, 0x80);
+    nes.run_instructions(5);
+    assert_eq!(nes.peek(0x10), 1);
+
+    nes.cpu_write(0x2000, 0x00);
+    nes.run_instructions(5);
+    nes.cpu_write(0x2000, 0x80);
+    nes.run_instructions(5);
+    assert_eq!(nes.peek(0x10), 2);
+
+    // Not after the flag has been acknowledged
+    nes.cpu_write(0x2000, 0x00);
+    nes.cpu_read(0x2002);
+    nes.cpu_write(0x2000, 0x80);
+    nes.run_instructions(5);
+    assert_eq!(nes.peek(0x10), 2);
+}
+
+#[test]
+fn sprites_on_scanline_ignores_the_limit() {
+    let mut nes = warmed_up_test_nes(&[]);
+    nes.cpu_write(0x2003, 0);
+    for i in 0..64 {
+        let sprite = if i < 10 {
+            [50, 1, 0, i * 16]
+        } else {
+            [0xFF; 4]
+        };
+        for byte in sprite {
+            nes.cpu_write(0x2004, byte);
+        }
+    }
+
+    assert_eq!(nes.sprites_on_scanline(50), 0);
+    assert_eq!(nes.sprites_on_scanline(51), 10);
+    assert_eq!(nes.sprites_on_scanline(58), 10);
+    assert_eq!(nes.sprites_on_scanline(59), 0);
+    assert_eq!(nes.sprites_on_scanline(0), 0);
+
+    // 8x16 sprites
+    nes.cpu_write(0x2000, 0x20);
+    assert_eq!(nes.sprites_on_scanline(66), 10);
+
+    // Only 8 of them are drawn
+    assert_eq!(visible_sprites(true).0, 8);
+}
+
+#[test]
+fn write_only_registers_read_the_latch() {
+    let mut nes = test_nes(&[]);
+
+    // Writing OAMADDR has no other visible effect
+    nes.cpu_write(0x2003, 0x5A);
+    for addr in [0x2000, 0x2001, 0x2003, 0x2005, 0x2006, 0x3FF8] {
+        assert_eq!(nes.cpu_read(addr), 0x5A, "${:04X}", addr);
+    }
+
+    // Mirror of $2003
+    nes.cpu_write(0x200B, 0xA5);
+    assert_eq!(nes.cpu_read(0x2000), 0xA5);
+}
+
+#[test]
+fn odd_frames_skip_a_dot() {
+    let mut nes = warmed_up_test_nes(&[]);
+    // Sprites only, the skip depends on any kind of rendering being enabled
+    nes.cpu_write(0x2001, 0x10);
+
+    let frame_dots = |nes: &mut Nes| {
+        step_ppu_to(nes, 0, 0);
+        let (start, odd) = (nes.ppu_dot_count(), nes.is_odd_frame());
+        nes.ppu_tick();
+        step_ppu_to(nes, 0, 0);
+        (odd, nes.ppu_dot_count() - start)
+    };
+
+    let first = frame_dots(&mut nes);
+    let second = frame_dots(&mut nes);
+    let (odd, even) = if first.0 {
+        (first, second)
+    } else {
+        (second, first)
+    };
+    assert!(odd.0 && !even.0);
+    assert_eq!(even.1, 341 * 262);
+    assert_eq!(odd.1, 341 * 262 - 1);
+
+    // No skip with rendering disabled
+    nes.cpu_write(0x2001, 0);
+    assert_eq!(frame_dots(&mut nes).1, 341 * 262);
+    assert_eq!(frame_dots(&mut nes).1, 341 * 262);
+}
+
+#[test]
+fn framebuffer_mut_lasts_until_the_next_frame() {
+    let mut nes = test_nes(&[]);
+    // Starts at the beginning of a frame, so the next one is drawn completely
+    nes.run_until(10, |nes| nes.ppu_warmed_up());
+    write_vram(&mut nes, 0x3F00, 0x21);
+    nes.cpu_write(0x2006, 0);
+    nes.cpu_write(0x2006, 0);
+    nes.run_one_frame();
+
+    let pixel = 100 * 256 + 100;
+    assert_eq!(nes.get_frame_buffer()[pixel], 0x21);
+    nes.framebuffer_mut()[pixel] = 0x30;
+    assert_eq!(nes.get_frame_buffer()[pixel], 0x30);
+
+    nes.run_one_frame();
+    assert_eq!(nes.get_frame_buffer()[pixel], 0x21);
+}
+
+#[test]
+fn framebuffer_scaled_repeats_pixels() {
+    let mut nes = test_nes(&[]);
+    nes.framebuffer_mut()[100 * 256 + 50] = 0x30;
+    nes.framebuffer_mut()[100 * 256 + 51] = 0x16;
+
+    let mut scaled = vec![0; 512 * 480];
+    nes.framebuffer_scaled(2, &mut scaled).unwrap();
+    for (x, y) in [(100, 200), (101, 200), (100, 201), (101, 201)] {
+        assert_eq!(scaled[y * 512 + x], 0x30);
+    }
+    assert_eq!(scaled[200 * 512 + 102], 0x16);
+    assert_eq!(scaled[202 * 512 + 100], 0);
+
+    let mut frame = vec![0xFF; 256 * 240];
+    nes.framebuffer_scaled(1, &mut frame).unwrap();
+    assert_eq!(frame, nes.get_frame_buffer());
+
+    assert!(matches!(
+        nes.framebuffer_scaled(0, &mut []),
+        Err(NesError::InvalidFrameScale { scale: 0, len: 0 })
+    ));
+    assert!(nes
+        .framebuffer_scaled(9, &mut vec![0; 256 * 240 * 81])
+        .is_err());
+    assert!(nes.framebuffer_scaled(2, &mut frame).is_err());
+}
+
+#[test]
+fn single_screen_switch_mid_frame() {
+    // AxROM with 32KB of PRG ROM
+    let mut rom = test_rom(&[]);
+    rom[4] = 2;
+    rom[6] = 0x70;
+    let prg = rom[16..].to_vec();
+    rom.extend_from_slice(&prg);
+
+    let mut nes = Nes::new(&rom).expect("error when creating test NES instance");
+    nes.run_until(10, |nes| nes.ppu_warmed_up());
+
+    // Nametable A is filled with the empty tile 0, nametable B with the solid tile 1
+    for addr in 0x10..0x18 {
+        write_vram(&mut nes, addr, 0xFF);
+    }
+    write_vram(&mut nes, 0x3F00, 0x0F);
+    write_vram(&mut nes, 0x3F01, 0x16);
+    nes.cpu_write(0x8000, 0x10);
+    for addr in 0x2000..0x23C0 {
+        write_vram(&mut nes, addr, 1);
+    }
+
+    nes.cpu_write(0x8000, 0);
+    nes.cpu_write(0x2006, 0);
+    nes.cpu_write(0x2006, 0);
+    nes.cpu_write(0x2001, 0x0A);
+    step_ppu_to(&mut nes, 0, 0);
+    step_ppu_to(&mut nes, 120, 0);
+    nes.cpu_write(0x8000, 0x10);
+    step_ppu_to(&mut nes, 240, 0);
+
+    let frame = nes.get_frame_buffer();
+    assert!(frame[100 * 256..101 * 256]
+        .iter()
+        .all(|&pixel| pixel == 0x0F));
+    assert!(frame[140 * 256..141 * 256]
+        .iter()
+        .all(|&pixel| pixel == 0x16));
+}
+
+#[test]
+fn step_ppu_dot_holds_the_cpu() {
+    let mut nes = test_nes(&[]);
+    while nes.ppu_position().0 != 100 {
+        nes.run_cpu_cycle();
+    }
+
+    let (scanline, dot) = nes.ppu_position();
+    let (cycles, dots) = (nes.get_cycle_count(), nes.ppu_dot_count());
+    for _ in 0..341 {
+        nes.step_ppu_dot();
+    }
+    assert_eq!(nes.ppu_position(), (scanline + 1, dot));
+    assert_eq!(nes.get_cycle_count(), cycles);
+
+    // The PPU waits for the CPU to catch up with the 341 dots
+    for _ in 0..113 {
+        nes.clock_ppu_apu();
+    }
+    assert_eq!(nes.ppu_dot_count(), dots + 341);
+    nes.clock_ppu_apu();
+    assert_eq!(nes.ppu_dot_count(), dots + 3 * 114);
+}
@@ -0,0 +1,67 @@
+use super::*;
+use crate::DebugSnapshot;
+
+/// Set to regenerate the golden files after an intentional behavior change
+const UPDATE_ENV: &str = "FEARLESS_NES_UPDATE_GOLDEN";
+
+fn golden_test(rom_path: &str, frames: u64, golden_path: &str) {
+    let rom = fs::read(test_path(rom_path)).unwrap();
+    let mut nes = Nes::new(&rom).expect("error when creating test NES instance");
+    for _ in 0..frames {
+        nes.run_one_frame();
+    }
+    let snapshot = nes.debug_snapshot();
+
+    let golden_path = test_path(golden_path);
+    if env::var_os(UPDATE_ENV).is_some() {
+        let json = serde_json::to_string_pretty(&snapshot).unwrap();
+        fs::write(&golden_path, json + "\n").unwrap();
+    }
+
+    let golden: DebugSnapshot =
+        serde_json::from_str(&fs::read_to_string(&golden_path).unwrap()).unwrap();
+
+    // Field by field for more readable failures
+    assert_eq!(snapshot.cpu, golden.cpu);
+    assert_eq!(snapshot.ppu, golden.ppu);
+    assert_eq!(snapshot.apu, golden.apu);
+    assert_eq!(snapshot.mapper, golden.mapper);
+    assert_eq!(snapshot.frame_count, golden.frame_count);
+    assert_eq!(snapshot.frame_hash, golden.frame_hash);
+}
+
+#[test]
+fn nestest_snapshot() {
+    golden_test(
+        "cpu/nestest/nestest.nes",
+        100,
+        "snapshot/nestest_100_frames.json",
+    );
+}
+
+#[cfg(feature = "json-state")]
+#[test]
+fn json_state_round_trip() {
+    let rom = fs::read(test_path("cpu/nestest/nestest.nes")).unwrap();
+    let mut nes = Nes::new(&rom).expect("error when creating test NES instance");
+    nes.run_until(30, |_| false);
+
+    let json = nes.save_state_json().unwrap();
+    let mut loaded = Nes::new(&rom).unwrap();
+    loaded.load_state_json(&json).unwrap();
+    assert_eq!(loaded.debug_snapshot(), nes.debug_snapshot());
+
+    for _ in 0..10 {
+        nes.run_one_frame();
+        loaded.run_one_frame();
+    }
+    assert_eq!(
+        loaded.debug_snapshot().frame_hash,
+        nes.debug_snapshot().frame_hash
+    );
+
+    assert!(matches!(
+        test_nes(&[]).load_state_json(&json),
+        Err(NesError::State(crate::StateError::CartridgeMismatch { .. }))
+    ));
+}